@@ -0,0 +1,285 @@
+use std::{ops::Sub, time::Duration};
+
+use glam::{Mat4, Vec2, Vec3, Vec4};
+use tokio::time::Instant;
+
+use crate::{
+    easing::Easing,
+    transform::{Motion, Transform},
+};
+
+// anything an Animation can move between.  Colors are Vec3 (rgb) or Vec4 (rgba), like the
+// ones the palette hands out
+pub trait Animatable: Copy
+{
+    // how fast the value is changing, per second
+    type Velocity: Copy + Sub<Output = Self::Velocity>;
+    const STILL: Self::Velocity;
+
+    // `amount` isn't clamped so eased curves can overshoot
+    fn interpolate(&self, other: &Self, amount: f32) -> Self;
+    fn moved(&self, velocity: &Self::Velocity, seconds: f32) -> Self;
+    fn velocity(from: &Self, to: &Self, seconds: f32) -> Self::Velocity;
+}
+
+macro_rules! linear {
+    ($($value:ty => $still:expr),*) => {
+        $(
+            impl Animatable for $value
+            {
+                type Velocity = $value;
+                const STILL: $value = $still;
+
+                fn interpolate(&self, other: &Self, amount: f32) -> Self
+                {
+                    *self + (*other - *self) * amount
+                }
+
+                fn moved(&self, velocity: &Self, seconds: f32) -> Self { *self + *velocity * seconds }
+
+                fn velocity(from: &Self, to: &Self, seconds: f32) -> Self { (*to - *from) / seconds }
+            }
+        )*
+    };
+}
+
+linear!(f32 => 0.0, Vec2 => Vec2::ZERO, Vec3 => Vec3::ZERO, Vec4 => Vec4::ZERO);
+
+impl Animatable for Transform
+{
+    type Velocity = Motion;
+
+    const STILL: Motion = Motion::ZERO;
+
+    fn interpolate(&self, other: &Self, amount: f32) -> Self
+    {
+        Transform::interpolate(self, other, amount)
+    }
+
+    fn moved(&self, velocity: &Motion, seconds: f32) -> Self
+    {
+        Transform::moved(self, velocity, seconds)
+    }
+
+    fn velocity(from: &Self, to: &Self, seconds: f32) -> Motion
+    {
+        Motion::between(from, to, seconds)
+    }
+}
+
+// matrices are taken apart and animated as a Transform, see there for why
+impl Animatable for Mat4
+{
+    type Velocity = Motion;
+
+    const STILL: Motion = Motion::ZERO;
+
+    fn interpolate(&self, other: &Self, amount: f32) -> Self
+    {
+        Transform::from_matrix(*self)
+            .interpolate(&Transform::from_matrix(*other), amount)
+            .matrix()
+    }
+
+    fn moved(&self, velocity: &Motion, seconds: f32) -> Self
+    {
+        Transform::from_matrix(*self)
+            .moved(velocity, seconds)
+            .matrix()
+    }
+
+    fn velocity(from: &Self, to: &Self, seconds: f32) -> Motion
+    {
+        Motion::between(
+            &Transform::from_matrix(*from),
+            &Transform::from_matrix(*to),
+            seconds,
+        )
+    }
+}
+
+// a value easing from `begin` to `end` over `duration`.  It can be retargeted at any time,
+// the new animation picks up from wherever the value is now and as fast as it's moving
+pub struct Animation<T: Animatable>
+{
+    pub begin:      T,
+    pub end:        T,
+    pub start_time: Instant,
+    pub duration:   Duration,
+    pub easing:     Easing,
+    // how much faster the value was moving when it was last retargeted than the new curve
+    // sets off.  It carries on into the new animation and fades out by the end of it
+    pub velocity:   T::Velocity,
+}
+
+impl<T: Animatable> Animation<T>
+{
+    // sitting still at `value` until told to move, then moving along `curve` for `length`
+    pub fn new(value: T, length: Duration, curve: Easing) -> Self
+    {
+        Self {
+            begin:      value,
+            end:        value,
+            start_time: Instant::now(),
+            duration:   length,
+            easing:     curve,
+            velocity:   T::STILL,
+        }
+    }
+
+    // make this the next value we will animate to and start the timer
+    pub fn next(&mut self, end: T) { self.next_after(end, Duration::ZERO); }
+
+    // like next, but the value holds where it is for `delay` before it sets off
+    pub fn next_after(&mut self, end: T, delay: Duration)
+    {
+        let now = Instant::now();
+        self.retarget(end, now, now + delay);
+    }
+
+    fn retarget(&mut self, end: T, now: Instant, start: Instant)
+    {
+        let velocity = self.velocity_at(now);
+        self.begin = self.value_at(now);
+        self.end = end;
+        self.start_time = start;
+        // the new curve already sets off at a speed of its own, only the difference is
+        // carried.  Carrying all of it on top would run past `end` on fast repeated moves
+        let slope = self.easing.initial_slope();
+        self.velocity = if slope > 0.0
+        {
+            velocity - T::velocity(&self.begin, &self.end, self.duration.as_secs_f32() / slope)
+        }
+        else
+        {
+            velocity
+        };
+    }
+
+    pub fn set(&mut self, begin: T, end: T)
+    {
+        self.begin = begin;
+        self.end = end;
+        self.velocity = T::STILL;
+        self.start_time = Instant::now();
+    }
+
+    pub fn value(&self) -> T { self.value_at(Instant::now()) }
+
+    // how far along the animation is, between 0.0 and 1.0
+    pub fn progress(&self) -> f32 { self.progress_at(Instant::now()) }
+
+    pub fn is_active(&self) -> bool { !self.is_done() }
+
+    pub fn is_done(&self) -> bool { self.start_time + self.duration < Instant::now() }
+
+    fn progress_at(&self, time: Instant) -> f32
+    {
+        let elapsed = time.saturating_duration_since(self.start_time);
+        (elapsed.as_secs_f32() / self.duration.as_secs_f32()).clamp(0.0, 1.0)
+    }
+
+    fn value_at(&self, time: Instant) -> T
+    {
+        let progress = self.progress_at(time);
+        // not clamped after easing so a bezier can overshoot
+        let eased = self.easing.apply(progress);
+        // the carried velocity is a hermite tangent, at full strength at the start and gone by
+        // the end so the value still lands exactly on `end`
+        let carried = self.duration.as_secs_f32() * progress * (1.0 - progress).powi(2);
+        self.begin
+            .interpolate(&self.end, eased)
+            .moved(&self.velocity, carried)
+    }
+
+    fn velocity_at(&self, time: Instant) -> T::Velocity
+    {
+        let step = Duration::from_millis(4);
+        match time.checked_sub(step)
+        {
+            Some(before) => T::velocity(
+                &self.value_at(before),
+                &self.value_at(time),
+                step.as_secs_f32(),
+            ),
+            None => T::STILL,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    const LENGTH: Duration = Duration::from_millis(250);
+
+    fn at(animation: &Animation<f32>, time: Instant) -> (f32, f32)
+    {
+        (animation.value_at(time), animation.velocity_at(time))
+    }
+
+    #[test]
+    fn retargeting_picks_up_where_the_value_is()
+    {
+        for curve in [
+            Easing::Linear,
+            Easing::CubicOut,
+            Easing::Spring { omega: 10.0 },
+        ]
+        {
+            let start = Instant::now();
+            let mut animation = Animation::new(0.0, LENGTH, curve);
+            animation.retarget(1.0, start, start);
+            let now = start + Duration::from_millis(100);
+            let (value, velocity) = at(&animation, now);
+            animation.retarget(-1.0, now, now);
+            assert_eq!(animation.value_at(now), value);
+            // the speed carries on into the new animation, even though it heads the other way
+            let step = Duration::from_micros(100);
+            let carried = (animation.value_at(now + step) - value) / step.as_secs_f32();
+            assert!(
+                (carried - velocity).abs() < velocity.abs() * 0.1,
+                "{:?} was moving at {} and set off at {}",
+                curve,
+                velocity,
+                carried
+            );
+            assert!((animation.value_at(now + LENGTH) + 1.0).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn a_still_animation_carries_no_speed()
+    {
+        let start = Instant::now();
+        let mut animation = Animation::new(0.0, LENGTH, Easing::CubicIn);
+        animation.retarget(1.0, start, start);
+        // a cubic sets off slowly, nothing is carried over to speed it up
+        let early = animation.value_at(start + LENGTH / 10);
+        assert!(early > 0.0 && early < 0.002, "{}", early);
+    }
+
+    #[test]
+    fn rapid_presses_never_overshoot()
+    {
+        for curve in [Easing::Linear, Easing::QuadOut, Easing::CubicOut]
+        {
+            let start = Instant::now();
+            let mut animation = Animation::new(0.0, LENGTH, curve);
+            // a key held down, one more tile every 40ms
+            for press in 1..=5
+            {
+                let now = start + Duration::from_millis(40 * (press - 1));
+                animation.retarget(press as f32, now, now);
+            }
+            let last = start + Duration::from_millis(160);
+            for step in 0..=100
+            {
+                let value = animation.value_at(last + LENGTH * step / 100);
+                assert!(value <= 5.0 + 1e-4, "{:?} overshot to {}", curve, value);
+            }
+            assert!((animation.value_at(last + LENGTH) - 5.0).abs() < 1e-5);
+        }
+    }
+}
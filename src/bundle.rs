@@ -0,0 +1,268 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::Error;
+
+use crate::{
+    data::{Set, HOME},
+    store,
+};
+
+// an offline demo bundle is the disk cache in a single file.  It starts with MAGIC, then
+// every entry is a little endian u32 name length, the name (a path relative to the cache
+// root such as `images/00ab...`), a little endian u64 data length and the data itself.  A
+// zero name length ends the entries, followed by a u64 count of them so a bundle cut short
+// anywhere, even between two entries, is caught
+const MAGIC: &[u8; 4] = b"LBB2";
+
+// packs the home feed, the feeds the sets were resolved from and the art (with previews and
+// palettes) of every set into `out`.  Other feeds lying around in the disk cache are left out.
+// The sets must already be in the disk cache, `lean-back bundle export` warms it first
+pub fn export(out: &Path, sets: &[Set]) -> Result<(usize, u64), Error>
+{
+    let mut paths = vec![store::feed_path(&HOME)];
+    for set in sets
+    {
+        if let Some(feed) = &set.feed
+        {
+            paths.push(store::feed_path(feed));
+        }
+        for item in &set.items
+        {
+            paths.push(store::image_path(&item.image_url));
+            paths.push(store::preview_path(&item.image_url));
+            paths.push(store::palette_path(&item.image_url));
+        }
+    }
+    paths.sort();
+    paths.dedup();
+    pack(out, store::root(), &paths)
+}
+
+// writes the files at `paths`, named relative to `root`, into a bundle
+fn pack(out: &Path, root: &Path, paths: &[PathBuf]) -> Result<(usize, u64), Error>
+{
+    let mut writer = BufWriter::new(File::create(out)?);
+    writer.write_all(MAGIC)?;
+    let mut count = 0;
+    let mut bytes = 0;
+    for path in paths
+    {
+        // art that failed to cache is left out, the demo shows its placeholder instead
+        let data = match std::fs::read(path)
+        {
+            Ok(data) => data,
+            Err(_) => continue,
+        };
+        let name = path
+            .strip_prefix(root)?
+            .to_string_lossy()
+            .replace('\\', "/");
+        writer.write_all(&(name.len() as u32).to_le_bytes())?;
+        writer.write_all(name.as_bytes())?;
+        writer.write_all(&(data.len() as u64).to_le_bytes())?;
+        writer.write_all(&data)?;
+        count += 1;
+        bytes += data.len() as u64;
+    }
+    writer.write_all(&0u32.to_le_bytes())?;
+    writer.write_all(&(count as u64).to_le_bytes())?;
+    writer.flush()?;
+    Ok((count, bytes))
+}
+
+// unpacks a bundle into `dir`, laid out exactly like the disk cache
+pub fn extract(bundle: &Path, dir: &Path) -> Result<usize, Error>
+{
+    let mut reader = BufReader::new(File::open(bundle)?);
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC
+    {
+        return Err(anyhow!("{} is not a lean-back bundle", bundle.display()));
+    }
+
+    // lengths are checked against what is left of the file before anything is allocated,
+    // so a corrupt length can't ask for more memory than the bundle could possibly hold
+    let mut remaining = std::fs::metadata(bundle)?.len() - MAGIC.len() as u64;
+    let mut count = 0;
+    loop
+    {
+        let length = u32::from_le_bytes(read_exact(&mut reader, &mut remaining)?);
+        if length == 0
+        {
+            break;
+        }
+        let name = read_entry(&mut reader, length as u64, &mut remaining)?;
+        let name = PathBuf::from(String::from_utf8(name)?);
+        // a bundle only ever writes inside its own directory
+        if !name
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+        {
+            return Err(anyhow!("bad entry {} in bundle", name.display()));
+        }
+
+        let length = u64::from_le_bytes(read_exact(&mut reader, &mut remaining)?);
+        let data = read_entry(&mut reader, length, &mut remaining)?;
+
+        let path = dir.join(name);
+        if let Some(parent) = path.parent()
+        {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, data)?;
+        count += 1;
+    }
+
+    let expected = u64::from_le_bytes(read_exact(&mut reader, &mut remaining)?);
+    if expected != count as u64 || remaining != 0
+    {
+        return Err(anyhow!(
+            "bundle is corrupt: {} entries of {} and {} stray bytes at the end",
+            count,
+            expected,
+            remaining
+        ));
+    }
+    Ok(count)
+}
+
+// a fixed size field of the bundle, or an error if the bundle ends before it
+fn read_exact<const N: usize>(reader: &mut impl Read, remaining: &mut u64)
+    -> Result<[u8; N], Error>
+{
+    let mut field = [0u8; N];
+    reader
+        .read_exact(&mut field)
+        .map_err(|_| anyhow!("bundle is truncated"))?;
+    *remaining = remaining.saturating_sub(N as u64);
+    Ok(field)
+}
+
+// the next `length` bytes of the bundle, or an error if the bundle ends before them
+fn read_entry(reader: &mut impl Read, length: u64, remaining: &mut u64) -> Result<Vec<u8>, Error>
+{
+    if length > *remaining
+    {
+        return Err(anyhow!(
+            "bundle entry of {} bytes runs past the end of the file",
+            length
+        ));
+    }
+    let mut data = Vec::with_capacity(length as usize);
+    reader.take(length).read_to_end(&mut data)?;
+    if data.len() as u64 != length
+    {
+        return Err(anyhow!("bundle is truncated"));
+    }
+    *remaining -= length;
+    Ok(data)
+}
+
+// runs the app from a bundle: it is unpacked into a scratch cache directory of this process's
+// own (emptied first so nothing from an earlier bundle leaks in) and the store stops going to
+// the network.  Has to be called before anything else uses the disk cache
+pub fn open(bundle: &Path) -> Result<(), Error>
+{
+    let dir = std::env::temp_dir().join(format!("lean-back-bundle-{}", std::process::id()));
+    std::fs::remove_dir_all(&dir).unwrap_or_default();
+    let count = extract(bundle, &dir)?;
+    println!(
+        "Running offline from {} ({} files)",
+        bundle.display(),
+        count
+    );
+    store::set_root(dir)?;
+    store::go_offline();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    // a scratch directory of the test's own, emptied first
+    fn scratch(name: &str) -> PathBuf
+    {
+        let dir =
+            std::env::temp_dir().join(format!("lean-back-test-{}-{}", std::process::id(), name));
+        std::fs::remove_dir_all(&dir).unwrap_or_default();
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    const FILES: [(&str, &[u8]); 3] = [
+        ("feeds/00ab.json", b"{\"data\": {}}"),
+        ("images/00cd", b"not really a jpeg"),
+        ("images/00cd.preview", b""),
+    ];
+
+    // packs FILES (and one that was never cached) into a bundle, returning its bytes
+    fn bundle(name: &str) -> (PathBuf, Vec<u8>)
+    {
+        let dir = scratch(name);
+        let root = dir.join("cache");
+        let mut paths = vec![root.join("images/00ef")];
+        for (name, data) in FILES
+        {
+            let path = root.join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, data).unwrap();
+            paths.push(path);
+        }
+        let out = dir.join("demo.lbb");
+        assert_eq!(pack(&out, &root, &paths).unwrap(), (3, 29));
+        let bytes = std::fs::read(&out).unwrap();
+        (dir, bytes)
+    }
+
+    #[test]
+    fn round_trip()
+    {
+        let (dir, _) = bundle("round-trip");
+        let into = dir.join("extracted");
+        assert_eq!(extract(&dir.join("demo.lbb"), &into).unwrap(), 3);
+        for (name, data) in FILES
+        {
+            assert_eq!(std::fs::read(into.join(name)).unwrap(), data);
+        }
+        assert!(!into.join("images/00ef").exists());
+    }
+
+    #[test]
+    fn cut_short_or_padded()
+    {
+        let (dir, bytes) = bundle("damaged");
+        let damaged = dir.join("damaged.lbb");
+        // the end marker and count are the last 12 bytes, so the first cut falls exactly
+        // between the last entry and the end of the bundle
+        let cuts = [
+            bytes[..bytes.len() - 12].to_vec(),
+            bytes[..bytes.len() - 1].to_vec(),
+            bytes[..MAGIC.len()].to_vec(),
+        ];
+        for cut in cuts
+        {
+            std::fs::write(&damaged, cut).unwrap();
+            assert!(extract(&damaged, &dir.join("cut")).is_err());
+        }
+        for padding in 1..4
+        {
+            let mut padded = bytes.clone();
+            padded.extend(vec![0; padding]);
+            std::fs::write(&damaged, padded).unwrap();
+            assert!(extract(&damaged, &dir.join("padded")).is_err());
+        }
+        // a count that doesn't match the entries
+        let mut miscounted = bytes.clone();
+        let count = miscounted.len() - 8;
+        miscounted[count] = 2;
+        std::fs::write(&damaged, miscounted).unwrap();
+        assert!(extract(&damaged, &dir.join("miscounted")).is_err());
+    }
+}
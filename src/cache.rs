@@ -1,11 +1,26 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    io::Cursor,
+    sync::{atomic::Ordering, Arc, Mutex},
+    time::Instant,
+};
+
 use anyhow::Error;
 use bytes::Bytes;
-use glium::glutin::event_loop::EventLoopProxy;
-use tokio::sync::mpsc;
+use image::{imageops::FilterType, DynamicImage, ImageFormat, ImageOutputFormat};
+use tokio::sync::{mpsc, Notify, OwnedSemaphorePermit, Semaphore};
 
-use crate::{data::Set, Call};
+use crate::{
+    data::Set,
+    loader::loader_for,
+    metrics::{Metrics, METRICS},
+    palette::Palette,
+    store,
+    throttle::Throttle,
+    Call, Listener,
+};
 
-pub fn cache_set(set: Set, cacher: mpsc::Sender<String>)
+pub fn cache_set(set: Set, cacher: Cacher)
 {
     let urls: Vec<String> = set
         .items
@@ -14,45 +29,952 @@ pub fn cache_set(set: Set, cacher: mpsc::Sender<String>)
         .collect();
     for url in urls
     {
-        // here we rely on our large queue size to prevent an overflow...
-        // if I had more time I would create a way to signal the UX that the cacher was overwellmed,
-        // instead of just 'dropping' these images
-        cacher.try_send(url).unwrap_or_default();
+        cacher.request(url);
+    }
+}
+
+// a handle to the cacher's queue.  The queue is unbounded so a request is never dropped, instead
+// the cacher coalesces duplicate urls (the same art shows up in many sets) into one download
+#[derive(Clone)]
+pub struct Cacher
+{
+    tx: mpsc::UnboundedSender<Request>,
+}
+
+impl Cacher
+{
+    pub fn request(&self, url: String) { self.send(Request::Fetch(url)); }
+
+    pub fn prefetch(&self, urls: Vec<String>)
+    {
+        if !urls.is_empty()
+        {
+            self.send(Request::Prefetch(urls));
+        }
+    }
+
+    fn send(&self, request: Request)
+    {
+        if self.tx.send(request).is_err()
+        {
+            eprintln!("the cacher has shut down, could not send a request");
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct CacherConfig
+{
+    // the maximum number of downloads in flight at any given time
+    pub concurrency: usize,
+    // the maximum number of downloads in flight against any single host
+    pub per_host:    usize,
+    // art wider than this is downscaled in the cacher, there is no point uploading 3000px
+    // images to draw them at tile size
+    pub max_width:   u32,
+    // bytes per second all downloads together may use, unlimited if None
+    pub bandwidth:   Option<usize>,
+    // for metered connections: art is capped at DATA_SAVER_WIDTH and nothing is prefetched
+    pub data_saver:  bool,
+}
+
+impl CacherConfig
+{
+    pub const DATA_SAVER_WIDTH: u32 = 320;
+
+    // reads LEAN_BACK_CONCURRENCY, LEAN_BACK_PER_HOST, LEAN_BACK_MAX_WIDTH, LEAN_BACK_BANDWIDTH
+    // and LEAN_BACK_DATA_SAVER, falling back to the defaults
+    pub fn from_env() -> Self
+    {
+        let default = Self::default();
+        let data_saver = env_flag("LEAN_BACK_DATA_SAVER");
+        let max_width = env_limit("LEAN_BACK_MAX_WIDTH")
+            .map(|width| width as u32)
+            .unwrap_or(default.max_width);
+        Self {
+            concurrency: env_limit("LEAN_BACK_CONCURRENCY").unwrap_or(default.concurrency),
+            per_host: env_limit("LEAN_BACK_PER_HOST").unwrap_or(default.per_host),
+            max_width: if data_saver
+            {
+                max_width.min(Self::DATA_SAVER_WIDTH)
+            }
+            else
+            {
+                max_width
+            },
+            bandwidth: env_limit("LEAN_BACK_BANDWIDTH"),
+            data_saver,
+        }
+    }
+}
+
+impl Default for CacherConfig
+{
+    fn default() -> Self
+    {
+        Self {
+            concurrency: 8,
+            per_host:    4,
+            max_width:   640,
+            bandwidth:   None,
+            data_saver:  false,
+        }
+    }
+}
+
+pub fn env_limit(name: &str) -> Option<usize>
+{
+    std::env::var(name)
+        .ok()
+        .and_then(|value| value.parse::<usize>().ok())
+        .filter(|value| *value > 0)
+}
+
+// set to anything but 0 or false to turn on
+pub fn env_flag(name: &str) -> bool
+{
+    match std::env::var(name)
+    {
+        Ok(value) => !matches!(value.to_lowercase().as_str(), "" | "0" | "false"),
+        Err(_) => false,
     }
 }
 
 // it's best to have only one cacher working at any given time, otherwise
 // they compete with each other for resources.  The goal of the cacher is to download and cache
-// texture files in the order of the queue.  simply send tx.send(url) and your texture will be ready in seconds (we hope!)
-pub async fn create_cacher(proxy: EventLoopProxy<Call>) -> mpsc::Sender<String>
+// texture files in the order of the queue.  simply call cacher.request(url) and your texture will be ready in seconds (we hope!)
+//
+// the cacher hands urls to a bounded pool of download workers.  Worker slots are claimed in queue order
+// so the queue's priority is still respected, while `per_host` keeps us from hammering any one server.
+// A url's host slot is claimed along with the worker and given back as soon as its download ends,
+// urls against a busy host wait their turn while the ones behind them go ahead, so a single slow
+// host can't tie up the whole pool.
+pub async fn create_cacher(listener: Listener, config: CacherConfig) -> Cacher
 {
-    let (tx, mut rx): (mpsc::Sender<String>, mpsc::Receiver<String>) = mpsc::channel(16 * 1024);
+    let (tx, mut rx): (
+        mpsc::UnboundedSender<Request>,
+        mpsc::UnboundedReceiver<Request>,
+    ) = mpsc::unbounded_channel();
     tokio::spawn(async move {
-        while let Option::Some(url) = rx.recv().await
+        let workers = Arc::new(Semaphore::new(config.concurrency));
+        let mut hosts: HashMap<String, Arc<Semaphore>> = HashMap::new();
+        let mut queue = Queue::default();
+        let shared = Shared {
+            listener,
+            max_width: config.max_width,
+            throttle: config
+                .bandwidth
+                .map(|bandwidth| Arc::new(Throttle::new(bandwidth))),
+            requested: Arc::new(Mutex::new(HashSet::new())),
+            progress: Arc::new(Mutex::new(Progress::default())),
+            finished: Arc::new(Notify::new()),
+            previews: Arc::new(Semaphore::new(config.concurrency)),
+        };
+        let mut open = true;
+        while open || !queue.is_empty()
         {
-            match fetch_image(url.clone()).await
+            if queue.is_empty()
             {
-                Ok(bytes) =>
+                match rx.recv().await
                 {
-                    proxy
-                        .send_event(Call::ToTexture { url, bytes })
-                        .unwrap_or_default();
+                    Some(request) => queue.add(request, &shared),
+                    None => open = false,
                 }
-                Err(_error) => eprintln!(
-                    "encountered an error when attempting to cache texture url: {}",
-                    url
-                ),
+                continue;
+            }
+            let worker = match workers.clone().acquire_owned().await
+            {
+                Ok(worker) => worker,
+                Err(_) => break,
+            };
+            // requests that arrived while we waited for a worker (prefetches in particular)
+            // may outrank whatever was at the front of the queue
+            while let Ok(request) = rx.try_recv()
+            {
+                queue.add(request, &shared);
             }
+            let (url, host) = match queue.next(&mut hosts, config.per_host, &shared.finished)
+            {
+                Some(next) => next,
+                None =>
+                {
+                    // every waiting url is against a busy host, give the worker back and look
+                    // again once a host slot frees up or a new request comes in
+                    drop(worker);
+                    if open
+                    {
+                        tokio::select! {
+                            _ = shared.finished.notified() => (),
+                            request = rx.recv() => match request
+                            {
+                                Some(request) => queue.add(request, &shared),
+                                None => open = false,
+                            },
+                        }
+                    }
+                    else
+                    {
+                        shared.finished.notified().await;
+                    }
+                    continue;
+                }
+            };
+            METRICS.queue_depth.fetch_sub(1, Ordering::Relaxed);
+            let shared = shared.clone();
+            tokio::spawn(async move {
+                cache_url(url, host, shared).await;
+                drop(worker);
+            });
         }
-        proxy
-            .send_event(Call::TextureCachingBatchComplete)
-            .unwrap_or_default();
     });
-    tx
+    Cacher { tx }
+}
+
+pub enum Request
+{
+    // cache this url once everything already queued has started
+    Fetch(String),
+    // cache these urls next, in this order, ahead of everything else in the queue
+    Prefetch(Vec<String>),
+}
+
+// the state every download worker shares with the cacher
+#[derive(Clone)]
+struct Shared
+{
+    listener:  Listener,
+    max_width: u32,
+    throttle:  Option<Arc<Throttle>>,
+    // every url that is queued, downloading or cached.  Failed urls are forgotten so they
+    // can be retried the next time they are requested
+    requested: Arc<Mutex<HashSet<String>>>,
+    progress:  Arc<Mutex<Progress>>,
+    // poked every time a download gives its host slot back
+    finished:  Arc<Notify>,
+    // previews are fetched outside the download workers, this keeps a cold run from asking
+    // the CDN for every thumbnail in the feed at once
+    previews:  Arc<Semaphore>,
+}
+
+// urls waiting for a download worker.  Every host has its own line, ordered by rank, so
+// finding the next url to start only looks at the front of each line and never parses a url
+// twice.  Local images (file: and data: urls) don't count against any host, they share the
+// line with no host
+#[derive(Default)]
+struct Queue
+{
+    lines: HashMap<Option<String>, VecDeque<Waiting>>,
+    // the ranks last handed out at the front and the back of the queue, lower goes first
+    front: i64,
+    back:  i64,
+    len:   usize,
+}
+
+struct Waiting
+{
+    rank: i64,
+    url:  String,
 }
 
-async fn fetch_image(url: String) -> Result<Bytes, Error>
+impl Queue
 {
-    let response = reqwest::get(url.clone()).await?;
-    Ok(response.bytes().await?)
+    fn is_empty(&self) -> bool { self.len == 0 }
+
+    fn add(&mut self, request: Request, shared: &Shared)
+    {
+        match request
+        {
+            Request::Fetch(url) =>
+            {
+                if Self::admit(&url, shared)
+                {
+                    self.back += 1;
+                    let waiting = Waiting {
+                        rank: self.back,
+                        url,
+                    };
+                    self.lines
+                        .entry(host_of(&waiting.url))
+                        .or_default()
+                        .push_back(waiting);
+                    self.len += 1;
+                }
+            }
+            Request::Prefetch(urls) =>
+            {
+                // walked backwards so the first url ends up at the very front
+                for url in urls.into_iter().rev()
+                {
+                    let host = host_of(&url);
+                    let line = self.lines.entry(host).or_default();
+                    let url = match line.iter().position(|waiting| waiting.url == url)
+                    {
+                        Some(index) => line.remove(index).map(|waiting| waiting.url),
+                        None if Self::admit(&url, shared) =>
+                        {
+                            Metrics::increment(&METRICS.prefetches);
+                            self.len += 1;
+                            Some(url)
+                        }
+                        None => None,
+                    };
+                    if let Some(url) = url
+                    {
+                        self.front -= 1;
+                        line.push_front(Waiting {
+                            rank: self.front,
+                            url,
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    // takes the highest ranked url whose host has a free slot, along with the slot
+    fn next(
+        &mut self,
+        hosts: &mut HashMap<String, Arc<Semaphore>>,
+        per_host: usize,
+        freed: &Arc<Notify>,
+    ) -> Option<(String, Option<HostSlot>)>
+    {
+        self.lines.retain(|_, line| !line.is_empty());
+        let mut best: Option<(&Option<String>, i64)> = None;
+        for (host, line) in &self.lines
+        {
+            let rank = line[0].rank;
+            if best.is_some_and(|(_, best)| best < rank)
+            {
+                continue;
+            }
+            let free = match host
+            {
+                Some(host) => hosts
+                    .get(host)
+                    .is_none_or(|slots| slots.available_permits() > 0),
+                None => true,
+            };
+            if free
+            {
+                best = Some((host, rank));
+            }
+        }
+        let host = best?.0.clone();
+        let slot = match &host
+        {
+            Some(host) =>
+            {
+                let slots = hosts
+                    .entry(host.clone())
+                    .or_insert_with(|| Arc::new(Semaphore::new(per_host)));
+                Some(HostSlot {
+                    _permit: slots.clone().try_acquire_owned().ok()?,
+                    freed:   freed.clone(),
+                })
+            }
+            None => None,
+        };
+        let waiting = self.lines.get_mut(&host)?.pop_front()?;
+        self.len -= 1;
+        Some((waiting.url, slot))
+    }
+
+    // false if the url is already queued, downloading or cached
+    fn admit(url: &str, shared: &Shared) -> bool
+    {
+        if !shared.requested.lock().unwrap().insert(url.to_string())
+        {
+            Metrics::increment(&METRICS.coalesced);
+            return false;
+        }
+        Metrics::increment(&METRICS.admitted);
+        METRICS.queue_depth.fetch_add(1, Ordering::Relaxed);
+        Progress::update(&shared.progress, &shared.listener, |progress| {
+            progress.queued += 1
+        });
+        tokio::spawn(send_placeholders(url.to_string(), shared.clone()));
+        true
+    }
+}
+
+// a download's claim on one of its host's slots.  The cacher is told the moment it is given
+// back, so a url waiting on the host can start while this one is still decoding
+struct HostSlot
+{
+    _permit: OwnedSemaphorePermit,
+    freed:   Arc<Notify>,
+}
+
+impl Drop for HostSlot
+{
+    fn drop(&mut self) { self.freed.notify_one(); }
+}
+
+async fn cache_url(url: String, host: Option<HostSlot>, shared: Shared)
+{
+    let max_width = shared.max_width;
+
+    let pixels = match load(&url, host, shared.throttle.clone()).await
+    {
+        Ok(loaded) =>
+        {
+            let size = loaded.bytes.len() as u64;
+            Progress::update(&shared.progress, &shared.listener, |progress| {
+                progress.bytes += size
+            });
+            // a preview is only made from the art when neither an earlier run nor the
+            // thumbnail has left one behind
+            let has_preview = store::has_preview(&url).await;
+            let has_palette = store::has_palette(&url).await;
+            // decoding is cpu bound so it is handed to tokio's blocking pool, keeping both
+            // the download workers and the render thread free of jpeg work
+            let start = Instant::now();
+            let from_disk = loaded.from_disk;
+            let decoded = tokio::task::spawn_blocking(move || {
+                decode(
+                    loaded.bytes,
+                    loaded.content_type,
+                    max_width,
+                    !has_preview,
+                    !has_palette,
+                )
+            })
+            .await
+            .unwrap_or_else(|error| Err(anyhow!(error)));
+            METRICS.decode_time.record(start.elapsed());
+            match decoded
+            {
+                Ok(decoded) =>
+                {
+                    if let Some(preview) = decoded.preview
+                    {
+                        store::write_preview(&url, &preview)
+                            .await
+                            .unwrap_or_default();
+                    }
+                    if let Some(palette) = decoded.palette
+                    {
+                        store::write_palette(&url, &palette.to_bytes())
+                            .await
+                            .unwrap_or_default();
+                        shared.listener.send_event(Call::Palette {
+                            url: url.clone(),
+                            palette,
+                        });
+                    }
+                    Ok(decoded.pixels)
+                }
+                Err(error) =>
+                {
+                    Metrics::increment(&METRICS.decode_errors);
+                    // a corrupt file on disk would fail forever, throw it away so the
+                    // next request downloads a fresh copy
+                    if from_disk && store::remove_image(&url).await.is_ok()
+                    {
+                        Metrics::increment(&METRICS.corrupt_removed);
+                    }
+                    Err(error)
+                }
+            }
+        }
+        Err(error) => Err(error),
+    };
+    match pixels
+    {
+        Ok(pixels) =>
+        {
+            shared.listener.send_event(Call::ToTexture { url, pixels });
+            Progress::update(&shared.progress, &shared.listener, |progress| {
+                progress.completed += 1
+            });
+        }
+        Err(error) =>
+        {
+            eprintln!(
+                "encountered an error when attempting to cache texture url: {} ERROR: {}",
+                url, error
+            );
+            shared.requested.lock().unwrap().remove(&url);
+            Progress::update(&shared.progress, &shared.listener, |progress| {
+                progress.failed += 1
+            });
+        }
+    }
+}
+
+// what a tile can show before its art arrives.  It is sent as soon as the url is admitted,
+// ahead of any download worker.  The colors saved on an earlier run go first, they tint the
+// tile before it has any pixels at all
+async fn send_placeholders(url: String, shared: Shared)
+{
+    let palette = store::read_palette(&url)
+        .await
+        .and_then(|bytes| Palette::from_bytes(&bytes));
+    if let Some(palette) = palette
+    {
+        shared.listener.send_event(Call::Palette {
+            url: url.clone(),
+            palette,
+        });
+    }
+    send_preview(url, shared).await;
+}
+
+// the blurred preview saved on an earlier run if there is one, otherwise the CDN's thumbnail
+// of the art, blurred and saved for next time
+async fn send_preview(url: String, shared: Shared)
+{
+    let _slot = match shared.previews.acquire().await
+    {
+        Ok(slot) => slot,
+        Err(_) => return,
+    };
+    let bytes = match store::read_preview(&url).await
+    {
+        Some(bytes) => bytes,
+        None => match fetch_thumbnail(&url, &shared).await
+        {
+            Ok(Some(bytes)) => bytes,
+            Ok(None) => return,
+            Err(error) =>
+            {
+                eprintln!("could not fetch the thumbnail of {}: {}", url, error);
+                return;
+            }
+        },
+    };
+    if let Ok(pixels) = decode_preview(bytes)
+    {
+        shared.listener.send_event(Call::Preview { url, pixels });
+    }
+}
+
+// None when the url has no thumbnail variant, or the network is off limits
+async fn fetch_thumbnail(url: &str, shared: &Shared) -> Result<Option<Bytes>, Error>
+{
+    let thumbnail = match thumbnail_url(url)
+    {
+        Some(thumbnail) if !store::is_offline() => thumbnail,
+        _ => return Ok(None),
+    };
+    let (parsed, loader) = loader_for(&thumbnail)?;
+    let (bytes, content_type) = loader.load(&parsed).await?;
+    if let Some(throttle) = &shared.throttle
+    {
+        throttle.spend(bytes.len());
+    }
+    let preview = tokio::task::spawn_blocking(move || {
+        let format = detect_format(&bytes, content_type)?;
+        preview(&image::load(Cursor::new(bytes), format)?)
+    })
+    .await??;
+    store::write_preview(url, &preview).await?;
+    Ok(Some(Bytes::from(preview)))
+}
+
+// the art CDN scales images on the fly to the `width` in the query, so any url with one has
+// a thumbnail variant the size of a preview.  Other urls only get a preview once their art
+// has been downloaded
+fn thumbnail_url(url: &str) -> Option<String>
+{
+    let mut url = reqwest::Url::parse(url).ok()?;
+    if !matches!(url.scheme(), "http" | "https")
+        || !url.query_pairs().any(|(key, _)| key == "width")
+    {
+        return None;
+    }
+    let pairs: Vec<(String, String)> = url
+        .query_pairs()
+        .map(|(key, value)| match key.as_ref()
+        {
+            "width" => (key.into_owned(), PREVIEW_WIDTH.to_string()),
+            _ => (key.into_owned(), value.into_owned()),
+        })
+        .collect();
+    url.query_pairs_mut().clear().extend_pairs(pairs);
+    Some(url.to_string())
+}
+
+struct Loaded
+{
+    bytes:        Bytes,
+    content_type: Option<String>,
+    from_disk:    bool,
+}
+
+// the disk cache is checked first, only a miss costs a network call.  The host's slot is given
+// back as soon as the disk cache answers.  Local images (file: and data: urls) skip both,
+// re-reading them is as cheap as the disk cache
+async fn load(
+    url: &str,
+    host: Option<HostSlot>,
+    throttle: Option<Arc<Throttle>>,
+) -> Result<Loaded, Error>
+{
+    let (parsed, loader) = loader_for(url)?;
+    if loader.is_remote()
+    {
+        if let Some(bytes) = store::read_image(url).await
+        {
+            Metrics::increment(&METRICS.disk_hits);
+            Metrics::add(&METRICS.bytes_from_disk, bytes.len() as u64);
+            return Ok(Loaded {
+                bytes,
+                content_type: None,
+                from_disk: true,
+            });
+        }
+        Metrics::increment(&METRICS.disk_misses);
+        if store::is_offline()
+        {
+            return Err(anyhow!("{} is not available offline", url));
+        }
+    }
+
+    // held until the download is done
+    let _host = host;
+    let start = Instant::now();
+    // local images cost no bandwidth
+    let loading = match throttle.filter(|_| loader.is_remote())
+    {
+        Some(throttle) => loader.load_throttled(&parsed, throttle),
+        None => loader.load(&parsed),
+    };
+    let (bytes, content_type) = match loading.await
+    {
+        Ok(fetched) => fetched,
+        Err(error) =>
+        {
+            if loader.is_remote()
+            {
+                Metrics::increment(&METRICS.download_errors);
+            }
+            return Err(error);
+        }
+    };
+    // only the network counts as a download
+    if loader.is_remote()
+    {
+        METRICS.download_latency.record(start.elapsed());
+        Metrics::increment(&METRICS.downloads);
+        Metrics::add(&METRICS.bytes_downloaded, bytes.len() as u64);
+        if let Err(error) = store::write_image(url, &bytes).await
+        {
+            eprintln!("could not save {} to the disk cache: {}", url, error);
+        }
+    }
+    Ok(Loaded {
+        bytes,
+        content_type,
+        from_disk: false,
+    })
+}
+
+// the host a url's download counts against, None for local images.  A url no loader takes
+// is left to fail in its own download
+fn host_of(url: &str) -> Option<String>
+{
+    match loader_for(url)
+    {
+        Ok((url, loader)) if loader.is_remote() =>
+        {
+            Some(url.host_str().unwrap_or_default().to_string())
+        }
+        Ok(_) => None,
+        Err(_) => Some(String::new()),
+    }
+}
+
+// a snapshot of the cacher's work so far, sent to the UX every time it changes
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Progress
+{
+    pub queued:    u64,
+    pub completed: u64,
+    pub failed:    u64,
+    // bytes loaded so far from the network or the disk cache, before decoding
+    pub bytes:     u64,
+}
+
+impl Progress
+{
+    pub fn is_done(&self) -> bool { self.completed + self.failed >= self.queued }
+
+    // how much of the queued work has finished, between 0.0 and 1.0
+    pub fn fraction(&self) -> f32
+    {
+        if self.queued == 0
+        {
+            return 1.0;
+        }
+        (self.completed + self.failed) as f32 / self.queued as f32
+    }
+
+    fn update(progress: &Mutex<Progress>, listener: &Listener, f: impl FnOnce(&mut Progress))
+    {
+        let snapshot = {
+            let mut progress = progress.lock().unwrap();
+            f(&mut progress);
+            *progress
+        };
+        listener.send_event(Call::CacheProgress(snapshot));
+    }
+}
+
+// a decoded RGBA image with its rows already flipped for openGL, ready to upload as a texture
+pub struct Pixels
+{
+    pub dimensions: (u32, u32),
+    pub data:       Vec<u8>,
+}
+
+struct Decoded
+{
+    pixels:  Pixels,
+    // an encoded preview for the disk cache, only made when there wasn't one already
+    preview: Option<Vec<u8>>,
+    // likewise only worked out when the disk cache didn't have it
+    palette: Option<Palette>,
+}
+
+fn decode(
+    bytes: Bytes,
+    content_type: Option<String>,
+    max_width: u32,
+    make_preview: bool,
+    make_palette: bool,
+) -> Result<Decoded, Error>
+{
+    let format = detect_format(&bytes, content_type)?;
+    let mut image = image::load(Cursor::new(bytes), format)?;
+    if image.width() > max_width
+    {
+        let height = (image.height() as u64 * max_width as u64 / image.width() as u64) as u32;
+        image = image.resize_exact(max_width, height.max(1), FilterType::Triangle);
+    }
+    let preview = if make_preview
+    {
+        Some(preview(&image)?)
+    }
+    else
+    {
+        None
+    };
+    let palette = if make_palette
+    {
+        Some(Palette::extract(&image))
+    }
+    else
+    {
+        None
+    };
+    Ok(Decoded {
+        pixels: to_pixels(image),
+        preview,
+        palette,
+    })
+}
+
+// the preview is a thumbnail a couple dozen pixels wide with a little blur on top, stored as a
+// png that is only a few hundred bytes.  Linear filtering smears it out nicely at tile size
+const PREVIEW_WIDTH: u32 = 24;
+
+fn preview(image: &DynamicImage) -> Result<Vec<u8>, Error>
+{
+    let thumbnail = image.thumbnail(PREVIEW_WIDTH, PREVIEW_WIDTH);
+    let blurred = image::imageops::blur(&thumbnail.to_rgba8(), 1.0);
+    let mut bytes = Cursor::new(vec![]);
+    DynamicImage::ImageRgba8(blurred).write_to(&mut bytes, ImageOutputFormat::Png)?;
+    Ok(bytes.into_inner())
+}
+
+fn decode_preview(bytes: Bytes) -> Result<Pixels, Error>
+{
+    Ok(to_pixels(image::load_from_memory_with_format(
+        &bytes,
+        ImageFormat::Png,
+    )?))
+}
+
+fn to_pixels(image: DynamicImage) -> Pixels
+{
+    let mut image = image.to_rgba8();
+    image::imageops::flip_vertical_in_place(&mut image);
+    Pixels {
+        dimensions: image.dimensions(),
+        data:       image.into_raw(),
+    }
+}
+
+// the magic bytes are trusted first since CDNs are not always honest about Content-Type,
+// the header is only consulted when the bytes are not recognized
+fn detect_format(bytes: &Bytes, content_type: Option<String>) -> Result<ImageFormat, Error>
+{
+    if let Ok(format) = image::guess_format(bytes)
+    {
+        return Ok(format);
+    }
+    content_type
+        .as_deref()
+        .and_then(format_from_mime)
+        .ok_or_else(|| {
+            anyhow!(
+                "unrecognized image format (Content-Type: {:?})",
+                content_type
+            )
+        })
+}
+
+fn format_from_mime(mime: &str) -> Option<ImageFormat>
+{
+    match mime.split(';').next()?.trim().to_lowercase().as_str()
+    {
+        "image/jpeg" | "image/jpg" => Some(ImageFormat::Jpeg),
+        "image/png" => Some(ImageFormat::Png),
+        "image/webp" => Some(ImageFormat::WebP),
+        "image/gif" => Some(ImageFormat::Gif),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::time::Duration;
+
+    use super::*;
+
+    // a 2x2 image, top row opaque red and half transparent green, bottom row fully transparent
+    // blue and opaque white.  Encoded with libwebp, once lossless and once lossy with an alpha chunk
+    const LOSSLESS_WEBP: [u8; 60] = [
+        82, 73, 70, 70, 52, 0, 0, 0, 87, 69, 66, 80, 86, 80, 56, 76, 39, 0, 0, 0, 47, 1, 64, 0, 16,
+        31, 48, 255, 2, 130, 34, 255, 71, 19, 16, 20, 249, 63, 154, 128, 160, 232, 186, 229, 2,
+        236, 166, 130, 154, 182, 13, 88, 252, 38, 29, 17, 253, 143, 3, 0,
+    ];
+    const LOSSY_ALPHA_WEBP: [u8; 124] = [
+        82, 73, 70, 70, 116, 0, 0, 0, 87, 69, 66, 80, 86, 80, 56, 88, 10, 0, 0, 0, 16, 0, 0, 0, 1,
+        0, 0, 1, 0, 0, 65, 76, 80, 72, 5, 0, 0, 0, 0, 255, 128, 0, 255, 0, 86, 80, 56, 32, 72, 0,
+        0, 0, 112, 2, 0, 157, 1, 42, 2, 0, 2, 0, 0, 192, 18, 37, 160, 2, 116, 186, 1, 248, 1, 248,
+        0, 3, 50, 149, 248, 192, 0, 254, 255, 26, 63, 137, 254, 163, 254, 10, 191, 241, 139, 255,
+        68, 223, 195, 186, 163, 33, 134, 42, 227, 181, 44, 127, 246, 179, 252, 227, 41, 252, 149,
+        127, 245, 191, 250, 130, 3, 244, 128, 0, 0, 0,
+    ];
+
+    fn shared() -> Shared
+    {
+        let (tx, _) = mpsc::unbounded_channel();
+        Shared {
+            listener:  Listener::Channel(tx),
+            max_width: 640,
+            throttle:  None,
+            requested: Arc::new(Mutex::new(HashSet::new())),
+            progress:  Arc::new(Mutex::new(Progress::default())),
+            finished:  Arc::new(Notify::new()),
+            previews:  Arc::new(Semaphore::new(1)),
+        }
+    }
+
+    fn fetch(url: &str) -> Request { Request::Fetch(url.to_string()) }
+
+    #[tokio::test]
+    async fn busy_hosts_are_skipped_in_rank_order()
+    {
+        let shared = shared();
+        let mut hosts = HashMap::new();
+        let mut queue = Queue::default();
+        for url in [
+            "https://a.test/1",
+            "https://a.test/2",
+            "https://b.test/1",
+            "data:,local",
+            "https://a.test/1",
+        ]
+        {
+            queue.add(fetch(url), &shared);
+        }
+        // the repeat was coalesced
+        assert_eq!(queue.len, 4);
+        queue.add(
+            Request::Prefetch(vec![
+                "https://b.test/2".to_string(),
+                "data:,local".to_string(),
+            ]),
+            &shared,
+        );
+        assert_eq!(queue.len, 5);
+
+        let mut next = || queue.next(&mut hosts, 1, &shared.finished);
+        let (url, b) = next().unwrap();
+        assert_eq!(url, "https://b.test/2");
+        let (url, local) = next().unwrap();
+        assert_eq!(url, "data:,local");
+        assert!(local.is_none());
+        let (url, a) = next().unwrap();
+        assert_eq!(url, "https://a.test/1");
+        // both hosts are busy
+        assert!(next().is_none());
+
+        // giving a slot back wakes the cacher up and lets the host's next url go
+        drop(a);
+        tokio::time::timeout(Duration::from_secs(1), shared.finished.notified())
+            .await
+            .unwrap();
+        let (url, _a) = next().unwrap();
+        assert_eq!(url, "https://a.test/2");
+        assert!(next().is_none());
+        drop(b);
+        assert_eq!(next().unwrap().0, "https://b.test/1");
+        assert!(queue.is_empty());
+    }
+
+    fn alphas(pixels: &Pixels) -> Vec<u8> { pixels.data.chunks(4).map(|pixel| pixel[3]).collect() }
+
+    #[test]
+    fn webp_keeps_its_alpha()
+    {
+        for webp in [&LOSSLESS_WEBP[..], &LOSSY_ALPHA_WEBP[..]]
+        {
+            let decoded = decode(Bytes::copy_from_slice(webp), None, 640, true, true).unwrap();
+            assert_eq!(decoded.pixels.dimensions, (2, 2));
+            // rows are flipped for openGL, the bottom row comes first
+            assert_eq!(alphas(&decoded.pixels), vec![0, 255, 255, 128]);
+            assert!(decoded.preview.is_some());
+        }
+        // lossless keeps the colors exactly too
+        let decoded = decode(Bytes::from_static(&LOSSLESS_WEBP), None, 640, false, false).unwrap();
+        assert_eq!(&decoded.pixels.data[8..12], &[255, 0, 0, 255]);
+        assert_eq!(&decoded.pixels.data[12..16], &[0, 255, 0, 128]);
+    }
+
+    #[test]
+    fn thumbnails_from_the_cdn()
+    {
+        assert_eq!(
+            thumbnail_url("https://cdn.test/v1/variant/abc/scale?format=jpeg&quality=90&width=500")
+                .as_deref(),
+            Some("https://cdn.test/v1/variant/abc/scale?format=jpeg&quality=90&width=24")
+        );
+        // nothing to scale, or not a web url
+        assert_eq!(thumbnail_url("https://cdn.test/art.jpg?format=jpeg"), None);
+        assert_eq!(thumbnail_url("file:///art.jpg?width=500"), None);
+        assert_eq!(thumbnail_url("data:,width=500"), None);
+    }
+
+    #[test]
+    fn format_from_the_bytes_before_the_header()
+    {
+        let bytes = Bytes::from_static(&LOSSLESS_WEBP);
+        assert_eq!(
+            detect_format(&bytes, Some("image/jpeg".to_string())).unwrap(),
+            ImageFormat::WebP
+        );
+        let unknown = Bytes::from_static(b"not an image");
+        assert_eq!(
+            detect_format(&unknown, Some("image/webp; charset=binary".to_string())).unwrap(),
+            ImageFormat::WebP
+        );
+        assert!(detect_format(&unknown, Some("text/html".to_string())).is_err());
+        assert!(detect_format(&unknown, None).is_err());
+    }
 }
@@ -0,0 +1,309 @@
+use std::{
+    collections::HashSet,
+    io::Write,
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Error;
+use tokio::sync::mpsc;
+
+use crate::{
+    bundle,
+    cache::{create_cacher, CacherConfig, Progress},
+    data::{self, Set},
+    palette::Palette,
+    store, Call, Listener,
+};
+
+const USAGE: &str = "usage:
+    lean-back cache warm                       resolve the feed and download all of its art
+    lean-back cache stats                      summarize what is in the disk cache
+    lean-back cache verify [--remove]          find truncated or corrupt files (and remove them)
+    lean-back cache purge --all                empty the disk cache
+    lean-back cache purge --older-than <days>  remove files that haven't been written in <days>
+    lean-back cache purge --max-size <MB>      remove the oldest files until the cache fits";
+
+// manages the disk cache without launching the UI: `lean-back cache <command>`
+pub async fn cache(args: &[String]) -> Result<(), Error>
+{
+    match args.first().map(String::as_str)
+    {
+        Some("warm") => match warm().await?
+        {
+            (_, Progress { failed: 0, .. }) => Ok(()),
+            (_, progress) => Err(anyhow!("{} images could not be cached", progress.failed)),
+        },
+        Some("stats") =>
+        {
+            stats();
+            Ok(())
+        }
+        Some("verify") => verify(args.iter().any(|arg| arg == "--remove")),
+        Some("purge") => purge(&args[1..]),
+        _ => Err(anyhow!(USAGE)),
+    }
+}
+
+// runs the same fetch and cacher the UI uses, but reports to a channel instead of the event loop.
+// The decoded pixels are simply dropped, what we are after is the disk cache they leave behind
+async fn warm() -> Result<(Vec<Set>, Progress), Error>
+{
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let listener = Listener::Channel(tx);
+    let cacher = create_cacher(listener.clone(), CacherConfig::from_env()).await;
+    let mut fetch = tokio::spawn(data::fetch(listener, cacher));
+    let mut fetched = false;
+
+    let mut warmed = Warmed::default();
+    // the cacher never hangs up, so we are done once the feed has been walked and the cacher
+    // has queued every url the feed asked for and finished each of them one way or another
+    while !(fetched && warmed.is_done())
+    {
+        tokio::select! {
+            result = &mut fetch, if !fetched =>
+            {
+                result??;
+                fetched = true;
+                // every set the feed produced is already waiting in the channel
+                while let Ok(call) = rx.try_recv()
+                {
+                    warmed.record(call);
+                }
+            }
+            call = rx.recv() => match call
+            {
+                Some(call) => warmed.record(call),
+                None => break,
+            },
+        }
+    }
+    println!();
+    Ok((warmed.sets, warmed.progress))
+}
+
+// what warm has heard from the fetch and the cacher so far
+#[derive(Default)]
+struct Warmed
+{
+    sets:     Vec<Set>,
+    // every image the sets asked the cacher for, duplicates are only queued once
+    urls:     HashSet<String>,
+    progress: Progress,
+}
+
+impl Warmed
+{
+    fn record(&mut self, call: Call)
+    {
+        match call
+        {
+            Call::AddSet(set) =>
+            {
+                self.urls
+                    .extend(set.items.iter().map(|item| item.image_url.clone()));
+                self.sets.push(set);
+            }
+            Call::CacheProgress(update) =>
+            {
+                self.progress = update;
+                print!(
+                    "\r{} sets, {}/{} images, {} failed, {} bytes",
+                    self.sets.len(),
+                    self.progress.completed,
+                    self.progress.queued,
+                    self.progress.failed,
+                    self.progress.bytes
+                );
+                std::io::stdout().flush().unwrap_or_default();
+            }
+            _ => (),
+        }
+    }
+
+    fn is_done(&self) -> bool
+    {
+        self.progress.queued >= self.urls.len() as u64 && self.progress.is_done()
+    }
+}
+
+// `lean-back bundle export <file>` warms the cache with the whole home collection and packs it
+// into a single file that `lean-back --bundle <file>` can run from without a network
+pub async fn bundle(args: &[String]) -> Result<(), Error>
+{
+    let out = match args
+    {
+        [command, out] if command == "export" => Path::new(out),
+        _ => return Err(anyhow!("usage: lean-back bundle export <file>")),
+    };
+    let (sets, progress) = warm().await?;
+    if progress.failed > 0
+    {
+        eprintln!(
+            "{} images could not be cached and are left out",
+            progress.failed
+        );
+    }
+    let (count, bytes) = bundle::export(out, &sets)?;
+    println!(
+        "wrote {} files, {} bytes to {}",
+        count,
+        bytes,
+        out.display()
+    );
+    Ok(())
+}
+
+fn stats()
+{
+    let files = store::list();
+    let mut images = (0, 0);
+    let mut previews = (0, 0);
+    let mut palettes = (0, 0);
+    let mut feeds = (0, 0);
+    let mut partials = (0, 0);
+    for file in &files
+    {
+        let bucket = match kind(&file.path)
+        {
+            Kind::Image => &mut images,
+            Kind::Preview => &mut previews,
+            Kind::Palette => &mut palettes,
+            Kind::Feed => &mut feeds,
+            Kind::Partial => &mut partials,
+        };
+        bucket.0 += 1;
+        bucket.1 += file.size;
+    }
+    println!("cache: {}", store::root().display());
+    println!("images:   {:>6} files {:>12} bytes", images.0, images.1);
+    println!("previews: {:>6} files {:>12} bytes", previews.0, previews.1);
+    println!("palettes: {:>6} files {:>12} bytes", palettes.0, palettes.1);
+    println!("feeds:    {:>6} files {:>12} bytes", feeds.0, feeds.1);
+    println!("partial:  {:>6} files {:>12} bytes", partials.0, partials.1);
+    if let Some(oldest) = files.iter().map(|file| file.modified).min()
+    {
+        println!("oldest:   {} days", age(oldest).as_secs() / DAY.as_secs());
+    }
+}
+
+// a file is bad if it was left half written or doesn't decode as the kind of file it should be
+fn verify(remove: bool) -> Result<(), Error>
+{
+    let mut bad = 0;
+    for file in store::list()
+    {
+        let problem = match kind(&file.path)
+        {
+            Kind::Partial => Some("truncated".to_string()),
+            Kind::Image | Kind::Preview => match std::fs::read(&file.path)
+            {
+                Ok(bytes) => image::load_from_memory(&bytes)
+                    .err()
+                    .map(|error| error.to_string()),
+                Err(error) => Some(error.to_string()),
+            },
+            Kind::Palette => match std::fs::read(&file.path)
+            {
+                Ok(bytes) => match Palette::from_bytes(&bytes)
+                {
+                    Some(_) => None,
+                    None => Some("not a palette".to_string()),
+                },
+                Err(error) => Some(error.to_string()),
+            },
+            Kind::Feed => match std::fs::read(&file.path)
+            {
+                Ok(bytes) => serde_json::from_slice::<serde_json::Value>(&bytes)
+                    .err()
+                    .map(|error| error.to_string()),
+                Err(error) => Some(error.to_string()),
+            },
+        };
+        if let Some(problem) = problem
+        {
+            bad += 1;
+            println!("{}: {}", file.path.display(), problem);
+            if remove
+            {
+                std::fs::remove_file(&file.path)?;
+            }
+        }
+    }
+    println!(
+        "{} bad files{}",
+        bad,
+        if remove && bad > 0 { " removed" } else { "" }
+    );
+    Ok(())
+}
+
+fn purge(args: &[String]) -> Result<(), Error>
+{
+    let mut files = store::list();
+    let doomed: Vec<&store::CachedFile> = match args
+    {
+        [flag] if flag == "--all" => files.iter().collect(),
+        [flag, days] if flag == "--older-than" =>
+        {
+            let max_age = DAY * days.parse::<u32>()?;
+            files
+                .iter()
+                .filter(|file| age(file.modified) > max_age)
+                .collect()
+        }
+        [flag, megabytes] if flag == "--max-size" =>
+        {
+            let limit = megabytes
+                .parse::<u64>()?
+                .checked_mul(1024 * 1024)
+                .ok_or_else(|| anyhow!("--max-size {} is too large", megabytes))?;
+            let mut total: u64 = files.iter().map(|file| file.size).sum();
+            // least recently written first
+            files.sort_by_key(|file| file.modified);
+            files
+                .iter()
+                .take_while(|file| {
+                    let over = total > limit;
+                    total = total.saturating_sub(file.size);
+                    over
+                })
+                .collect()
+        }
+        _ => return Err(anyhow!(USAGE)),
+    };
+
+    let mut bytes = 0;
+    for file in &doomed
+    {
+        std::fs::remove_file(&file.path)?;
+        bytes += file.size;
+    }
+    println!("removed {} files, {} bytes", doomed.len(), bytes);
+    Ok(())
+}
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+fn age(modified: SystemTime) -> Duration { modified.elapsed().unwrap_or(Duration::ZERO) }
+
+enum Kind
+{
+    Image,
+    Preview,
+    Palette,
+    Feed,
+    Partial,
+}
+
+fn kind(path: &Path) -> Kind
+{
+    match path.extension().and_then(|extension| extension.to_str())
+    {
+        Some("partial") => Kind::Partial,
+        Some("preview") => Kind::Preview,
+        Some("palette") => Kind::Palette,
+        Some("json") => Kind::Feed,
+        _ => Kind::Image,
+    }
+}
@@ -1,22 +1,18 @@
 use anyhow::Error;
-use glium::backend::glutin::glutin::event_loop::EventLoopProxy;
 use serde_json::Value;
 
 use crate::{
-    cache::{cache_set, create_cacher},
-    Call,
+    cache::{cache_set, Cacher},
+    store, Call, Listener,
 };
 
 lazy_static! {
     pub static ref HOME: &'static str = "https://cd-static.bamgrid.com/dp-117731241344/home.json";
 }
 
-pub async fn fetch(event_loop_proxy: EventLoopProxy<Call>) -> Result<(), Error>
+pub async fn fetch(listener: Listener, cacher: Cacher) -> Result<(), Error>
 {
-    let cache_proxy = event_loop_proxy.clone();
-    let cacher = create_cacher(cache_proxy).await;
-    let response = reqwest::get(HOME.to_string()).await?;
-    let json: Value = serde_json::from_str(response.text().await?.as_str())?;
+    let json: Value = serde_json::from_str(fetch_feed(&HOME).await?.as_str())?;
 
     if let Value::Array(containers) = &json["data"]["StandardCollection"]["containers"]
     {
@@ -29,9 +25,7 @@ pub async fn fetch(event_loop_proxy: EventLoopProxy<Call>) -> Result<(), Error>
                 {
                     let mut set = Set::new(title.clone());
                     set.items = parse_items(items).await;
-                    event_loop_proxy
-                        .send_event(Call::AddSet(set.clone()))
-                        .unwrap_or_default();
+                    listener.send_event(Call::AddSet(set.clone()));
                     cache_set(set, cacher.clone());
                 }
                 else
@@ -39,9 +33,7 @@ pub async fn fetch(event_loop_proxy: EventLoopProxy<Call>) -> Result<(), Error>
                     if let Value::String(ref_id) = container["set"]["refId"].clone()
                     {
                         let set = get_set(ref_id, title.clone()).await?;
-                        event_loop_proxy
-                            .send_event(Call::AddSet(set.clone()))
-                            .unwrap_or_default();
+                        listener.send_event(Call::AddSet(set.clone()));
                         cache_set(set, cacher.clone());
                     }
                     else
@@ -61,8 +53,7 @@ async fn get_set(ref_id: String, title: String) -> Result<Set, Error>
         "https://cd-static.bamgrid.com/dp-117731241344/sets/{}.json",
         ref_id
     );
-    let response = reqwest::get(url.to_string()).await?;
-    let response = response.text().await?;
+    let response = fetch_feed(&url).await?;
 
     let json: Value = serde_json::from_str(response.as_str())?;
     let mut set = json["data"]["CuratedSet"].clone();
@@ -86,6 +77,7 @@ async fn get_set(ref_id: String, title: String) -> Result<Set, Error>
     {
         let mut set = Set::new(title);
         set.items = parse_items(items).await;
+        set.feed = Some(url);
         return Ok(set);
     }
     else
@@ -95,35 +87,95 @@ async fn get_set(ref_id: String, title: String) -> Result<Set, Error>
     }
 }
 
+// every feed is saved to the disk cache when it is fetched, and read back from there
+// whenever the network lets us down
+async fn fetch_feed(url: &str) -> Result<String, Error>
+{
+    if store::is_offline()
+    {
+        return match store::read_feed(url).await
+        {
+            Some(bytes) => Ok(String::from_utf8(bytes.to_vec())?),
+            None => Err(anyhow!("{} is not available offline", url)),
+        };
+    }
+    let downloaded = async {
+        let response = reqwest::get(url).await?.error_for_status()?;
+        Ok::<String, Error>(response.text().await?)
+    };
+    match downloaded.await
+    {
+        Ok(text) =>
+        {
+            if let Err(error) = store::write_feed(url, text.as_bytes()).await
+            {
+                eprintln!("could not save {} to the disk cache: {}", url, error);
+            }
+            Ok(text)
+        }
+        Err(error) => match store::read_feed(url).await
+        {
+            Some(bytes) =>
+            {
+                println!("using the cached copy of {} ({})", url, error);
+                Ok(String::from_utf8(bytes.to_vec())?)
+            }
+            None => Err(error),
+        },
+    }
+}
+
 async fn parse_items(items: Vec<Value>) -> Vec<Item>
 {
     let mut rtn = vec![];
     for item in &items
     {
         // not my most elegant code, but i'm a bit surprised by the unexpected variety of image types (series,program,default)
-        if let Value::String(image_url) =
+        let image_url = if let Value::String(image_url) =
             item["image"]["tile"]["1.78"]["series"]["default"]["url"].clone()
         {
-            rtn.push(Item { image_url });
+            image_url
         }
         else if let Value::String(image_url) =
             item["image"]["tile"]["1.78"]["program"]["default"]["url"].clone()
         {
-            rtn.push(Item { image_url });
+            image_url
         }
         else if let Value::String(image_url) =
             item["image"]["tile"]["1.78"]["default"]["default"]["url"].clone()
         {
-            rtn.push(Item { image_url });
+            image_url
         }
         else
         {
             println!("could not find image_url for Item...");
-        }
+            continue;
+        };
+        rtn.push(Item {
+            image_url,
+            title: by_kind(&item["text"]["title"]["full"]),
+            year: item["releases"][0]["releaseYear"]
+                .as_u64()
+                .map(|year| year as u32),
+            rating: item["ratings"][0]["value"].as_str().map(String::from),
+            runtime: item["mediaMetadata"]["runtimeMillis"]
+                .as_u64()
+                .map(|millis| (millis / 60_000) as u32),
+            description: by_kind(&item["text"]["description"]["medium"]),
+        });
     }
     rtn
 }
 
+// text in the feed is keyed by the kind of content it describes, just like the images
+fn by_kind(value: &Value) -> Option<String>
+{
+    ["series", "program", "collection", "default"]
+        .iter()
+        .find_map(|kind| value[kind]["default"]["content"].as_str())
+        .map(String::from)
+}
+
 pub struct Data
 {
     pub sets: Vec<Set>,
@@ -139,6 +191,9 @@ pub struct Set
 {
     pub title: String,
     pub items: Vec<Item>,
+    // the feed the items were resolved from, when the set has one of its own rather than
+    // being spelled out in the home feed
+    pub feed:  Option<String>,
 }
 
 impl Set
@@ -148,6 +203,7 @@ impl Set
         Self {
             title,
             items: vec![],
+            feed: None,
         }
     }
 }
@@ -155,5 +211,35 @@ impl Set
 #[derive(Clone, Hash, Eq, PartialEq, Ord, PartialOrd)]
 pub struct Item
 {
-    pub image_url: String,
+    pub image_url:   String,
+    // everything below is optional, not every kind of item carries it
+    pub title:       Option<String>,
+    pub year:        Option<u32>,
+    pub rating:      Option<String>,
+    // in minutes
+    pub runtime:     Option<u32>,
+    // a paragraph or so, shown in the detail view
+    pub description: Option<String>,
+}
+
+impl Item
+{
+    // the line under the title, e.g. "2019 · TV-PG · 1h 52m"
+    pub fn details(&self) -> String
+    {
+        let runtime = self.runtime.map(|minutes| match minutes / 60
+        {
+            0 => format!("{}m", minutes),
+            hours => format!("{}h {}m", hours, minutes % 60),
+        });
+        let details: Vec<String> = [
+            self.year.map(|year| year.to_string()),
+            self.rating.clone(),
+            runtime,
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        details.join(" \u{b7} ")
+    }
 }
@@ -0,0 +1,227 @@
+// how an animation moves from start to finish.  Each curve maps linear progress in [0, 1] to
+// how far along the value is, 0.0 at the start and 1.0 at the end (a bezier may overshoot)
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Easing
+{
+    Linear,
+    QuadIn,
+    QuadOut,
+    QuadInOut,
+    CubicIn,
+    CubicOut,
+    CubicInOut,
+    // the CSS cubic-bezier(x1, y1, x2, y2), the curve runs from (0, 0) to (1, 1)
+    Bezier(f32, f32, f32, f32),
+    // a critically damped spring, as fast as a spring gets without bouncing.  `omega` is how
+    // stiff it is over the whole animation, 10 or so settles right as the duration runs out
+    Spring
+    {
+        omega: f32,
+    },
+}
+
+impl Easing
+{
+    pub fn apply(&self, progress: f32) -> f32
+    {
+        let t = progress.clamp(0.0, 1.0);
+        match *self
+        {
+            Easing::Linear => t,
+            Easing::QuadIn => t * t,
+            Easing::QuadOut => 1.0 - (1.0 - t) * (1.0 - t),
+            Easing::QuadInOut =>
+            {
+                if t < 0.5
+                {
+                    2.0 * t * t
+                }
+                else
+                {
+                    1.0 - (-2.0 * t + 2.0).powi(2) / 2.0
+                }
+            }
+            Easing::CubicIn => t * t * t,
+            Easing::CubicOut => 1.0 - (1.0 - t).powi(3),
+            Easing::CubicInOut =>
+            {
+                if t < 0.5
+                {
+                    4.0 * t * t * t
+                }
+                else
+                {
+                    1.0 - (-2.0 * t + 2.0).powi(3) / 2.0
+                }
+            }
+            Easing::Bezier(x1, y1, x2, y2) => bezier(t, x1, y1, x2, y2),
+            Easing::Spring { omega } =>
+            {
+                // the spring never quite arrives, so it is snapped into place at the end
+                if t >= 1.0
+                {
+                    1.0
+                }
+                else
+                {
+                    1.0 - (1.0 + omega * t) * (-omega * t).exp()
+                }
+            }
+        }
+    }
+}
+
+impl Easing
+{
+    // how fast the curve sets off, in progress per unit of progress
+    pub fn initial_slope(&self) -> f32
+    {
+        const STEP: f32 = 1e-4;
+        self.apply(STEP) / STEP
+    }
+}
+
+// one coordinate of the bezier at `s`, with the end points fixed at 0 and 1
+fn cubic(s: f32, p1: f32, p2: f32) -> f32
+{
+    let inverse = 1.0 - s;
+    3.0 * inverse * inverse * s * p1 + 3.0 * inverse * s * s * p2 + s * s * s
+}
+
+fn bezier(t: f32, x1: f32, y1: f32, x2: f32, y2: f32) -> f32
+{
+    // find the s where the curve's x is t, then the answer is y at that s.  Newton's method
+    // usually gets there in a few steps, bisection picks up the cases where it stalls
+    let mut s = t;
+    for _ in 0..8
+    {
+        let error = cubic(s, x1, x2) - t;
+        if error.abs() < 1e-5
+        {
+            return cubic(s, y1, y2);
+        }
+        let inverse = 1.0 - s;
+        let slope =
+            3.0 * inverse * inverse * x1 + 6.0 * inverse * s * (x2 - x1) + 3.0 * s * s * (1.0 - x2);
+        if slope.abs() < 1e-6
+        {
+            break;
+        }
+        s -= error / slope;
+    }
+
+    let (mut low, mut high) = (0.0, 1.0);
+    s = t;
+    for _ in 0..32
+    {
+        let x = cubic(s, x1, x2);
+        if (x - t).abs() < 1e-5
+        {
+            break;
+        }
+        if x < t
+        {
+            low = s;
+        }
+        else
+        {
+            high = s;
+        }
+        s = (low + high) / 2.0;
+    }
+    cubic(s, y1, y2)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    const CURVES: [Easing; 9] = [
+        Easing::Linear,
+        Easing::QuadIn,
+        Easing::QuadOut,
+        Easing::QuadInOut,
+        Easing::CubicIn,
+        Easing::CubicOut,
+        Easing::CubicInOut,
+        Easing::Bezier(0.25, 0.1, 0.25, 1.0),
+        Easing::Spring { omega: 10.0 },
+    ];
+
+    #[test]
+    fn every_curve_starts_at_0_and_ends_at_1()
+    {
+        for curve in CURVES
+        {
+            assert!(curve.apply(0.0).abs() < 1e-5, "{:?} starts off 0", curve);
+            assert!(
+                (curve.apply(1.0) - 1.0).abs() < 1e-5,
+                "{:?} ends off 1",
+                curve
+            );
+        }
+    }
+
+    #[test]
+    fn progress_is_clamped()
+    {
+        for curve in CURVES
+        {
+            assert_eq!(curve.apply(-1.0), curve.apply(0.0));
+            assert_eq!(curve.apply(2.0), curve.apply(1.0));
+        }
+    }
+
+    // CSS's `ease` keyword is cubic-bezier(0.25, 0.1, 0.25, 1.0), the expected values are
+    // what browsers compute for it
+    #[test]
+    fn bezier_matches_css_ease()
+    {
+        let ease = Easing::Bezier(0.25, 0.1, 0.25, 1.0);
+        for (progress, expected) in [(0.1, 0.0948), (0.25, 0.4094), (0.5, 0.8024), (0.75, 0.9604)]
+        {
+            let actual = ease.apply(progress);
+            assert!(
+                (actual - expected).abs() < 1e-3,
+                "ease({}) is {}, not {}",
+                progress,
+                actual,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn initial_slopes()
+    {
+        let slopes = [
+            (Easing::Linear, 1.0),
+            (Easing::QuadIn, 0.0),
+            (Easing::QuadOut, 2.0),
+            (Easing::CubicIn, 0.0),
+            (Easing::CubicOut, 3.0),
+            (Easing::Spring { omega: 10.0 }, 0.0),
+        ];
+        for (curve, slope) in slopes
+        {
+            assert!(
+                (curve.initial_slope() - slope).abs() < 0.02,
+                "{:?} sets off at {}",
+                curve,
+                curve.initial_slope()
+            );
+        }
+    }
+
+    #[test]
+    fn bezier_linear_control_points_are_linear()
+    {
+        let linear = Easing::Bezier(0.0, 0.0, 1.0, 1.0);
+        for step in 0..=10
+        {
+            let progress = step as f32 / 10.0;
+            assert!((linear.apply(progress) - progress).abs() < 1e-4);
+        }
+    }
+}
@@ -0,0 +1,283 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, RwLock},
+};
+
+use anyhow::Error;
+use bytes::{Bytes, BytesMut};
+use reqwest::Url;
+
+use crate::throttle::Throttle;
+
+lazy_static! {
+    static ref LOADERS: RwLock<HashMap<String, Arc<dyn ImageLoader>>> = RwLock::new(defaults());
+}
+
+// the bytes of an image and its content type, if the source knows it
+pub type Loading = Pin<Box<dyn Future<Output = Result<(Bytes, Option<String>), Error>> + Send>>;
+
+// fetches the raw bytes behind an image url.  Loaders are picked by the url's scheme, so fixtures
+// and bundled art can be served from files or inline data without a web server
+pub trait ImageLoader: Send + Sync
+{
+    fn load(&self, url: &Url) -> Loading;
+
+    // like load, but under a bandwidth cap.  Loaders that can should charge the throttle
+    // as the bytes come in, the rest are charged for everything at the end
+    fn load_throttled(&self, url: &Url, throttle: Arc<Throttle>) -> Loading
+    {
+        let loading = self.load(url);
+        Box::pin(async move {
+            throttle.wait().await;
+            let (bytes, content_type) = loading.await?;
+            throttle.spend(bytes.len());
+            Ok((bytes, content_type))
+        })
+    }
+
+    // remote images are worth saving to the disk cache and take a slot against their host,
+    // local ones are already as close as they are going to get
+    fn is_remote(&self) -> bool { true }
+}
+
+// makes `loader` responsible for every url with this scheme, replacing any built in loader
+pub fn register(scheme: &str, loader: Arc<dyn ImageLoader>)
+{
+    LOADERS
+        .write()
+        .unwrap()
+        .insert(scheme.to_lowercase(), loader);
+}
+
+pub fn loader_for(url: &str) -> Result<(Url, Arc<dyn ImageLoader>), Error>
+{
+    let url = Url::parse(url)?;
+    match LOADERS.read().unwrap().get(url.scheme())
+    {
+        Some(loader) => Ok((url.clone(), loader.clone())),
+        None => Err(anyhow!("no image loader for {}: urls", url.scheme())),
+    }
+}
+
+fn defaults() -> HashMap<String, Arc<dyn ImageLoader>>
+{
+    let mut loaders: HashMap<String, Arc<dyn ImageLoader>> = HashMap::new();
+    loaders.insert("http".to_string(), Arc::new(HttpLoader));
+    loaders.insert("https".to_string(), Arc::new(HttpLoader));
+    loaders.insert("file".to_string(), Arc::new(FileLoader));
+    loaders.insert("data".to_string(), Arc::new(DataLoader));
+    loaders
+}
+
+pub struct HttpLoader;
+
+impl ImageLoader for HttpLoader
+{
+    fn load(&self, url: &Url) -> Loading
+    {
+        let url = url.clone();
+        Box::pin(async move {
+            let response = reqwest::get(url).await?.error_for_status()?;
+            let content_type = content_type(&response);
+            Ok((response.bytes().await?, content_type))
+        })
+    }
+
+    // the body is read a chunk at a time, and each chunk has to be paid for before the next
+    fn load_throttled(&self, url: &Url, throttle: Arc<Throttle>) -> Loading
+    {
+        let url = url.clone();
+        Box::pin(async move {
+            throttle.wait().await;
+            let mut response = reqwest::get(url).await?.error_for_status()?;
+            let content_type = content_type(&response);
+            let mut bytes = BytesMut::new();
+            while let Some(chunk) = response.chunk().await?
+            {
+                bytes.extend_from_slice(&chunk);
+                throttle.take(chunk.len()).await;
+            }
+            Ok((bytes.freeze(), content_type))
+        })
+    }
+}
+
+fn content_type(response: &reqwest::Response) -> Option<String>
+{
+    response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_string())
+}
+
+// file:///absolute/path.jpg, the format is sniffed from the bytes
+pub struct FileLoader;
+
+impl ImageLoader for FileLoader
+{
+    fn load(&self, url: &Url) -> Loading
+    {
+        let path = url.to_file_path();
+        let url = url.to_string();
+        Box::pin(async move {
+            let path = path.map_err(|_| anyhow!("{} is not a local file", url))?;
+            Ok((Bytes::from(tokio::fs::read(path).await?), None))
+        })
+    }
+
+    fn is_remote(&self) -> bool { false }
+}
+
+// data:[<mime type>][;base64],<data> as described in RFC 2397
+pub struct DataLoader;
+
+impl ImageLoader for DataLoader
+{
+    fn load(&self, url: &Url) -> Loading
+    {
+        let decoded = decode_data_url(url.as_str());
+        Box::pin(async move { decoded })
+    }
+
+    fn is_remote(&self) -> bool { false }
+}
+
+fn decode_data_url(url: &str) -> Result<(Bytes, Option<String>), Error>
+{
+    let rest = url
+        .strip_prefix("data:")
+        .ok_or_else(|| anyhow!("not a data url"))?;
+    let (header, data) = rest
+        .split_once(',')
+        .ok_or_else(|| anyhow!("data url has no ','"))?;
+    let (mime, is_base64) = match header.strip_suffix(";base64")
+    {
+        Some(mime) => (mime, true),
+        None => (header, false),
+    };
+    let content_type = match mime
+    {
+        "" => None,
+        mime => Some(mime.to_string()),
+    };
+    let data = percent_decode(data);
+    let bytes = if is_base64
+    {
+        base64_decode(&data)?
+    }
+    else
+    {
+        data
+    };
+    Ok((Bytes::from(bytes), content_type))
+}
+
+fn percent_decode(text: &str) -> Vec<u8>
+{
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len()
+    {
+        let escaped = bytes
+            .get(index + 1..index + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[index], escaped)
+        {
+            (b'%', Some(byte)) =>
+            {
+                out.push(byte);
+                index += 3;
+            }
+            (byte, _) =>
+            {
+                out.push(byte);
+                index += 1;
+            }
+        }
+    }
+    out
+}
+
+// the standard or the url safe alphabet, padding optional and whitespace ignored
+fn base64_decode(text: &[u8]) -> Result<Vec<u8>, Error>
+{
+    let mut out = Vec::with_capacity(text.len() * 3 / 4);
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for byte in text
+    {
+        let value = match byte
+        {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            b'=' => break,
+            b' ' | b'\n' | b'\r' | b'\t' => continue,
+            _ => return Err(anyhow!("invalid base64 in data url")),
+        };
+        buffer = (buffer << 6) | value as u32;
+        bits += 6;
+        if bits >= 8
+        {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn base64_with_and_without_padding()
+    {
+        let (bytes, content_type) = decode_data_url("data:image/png;base64,aGVsbG8=").unwrap();
+        assert_eq!(&bytes[..], b"hello");
+        assert_eq!(content_type.as_deref(), Some("image/png"));
+
+        let (bytes, _) = decode_data_url("data:image/png;base64,aGVsbG8").unwrap();
+        assert_eq!(&bytes[..], b"hello");
+    }
+
+    #[test]
+    fn base64_url_safe_alphabet()
+    {
+        assert_eq!(base64_decode(b"+/8=").unwrap(), vec![0xfb, 0xff]);
+        assert_eq!(base64_decode(b"-_8").unwrap(), vec![0xfb, 0xff]);
+        assert!(base64_decode(b"a*b=").is_err());
+    }
+
+    #[test]
+    fn percent_escapes()
+    {
+        assert_eq!(percent_decode("hello%20world%2c"), b"hello world,");
+        // not an escape, left as is
+        assert_eq!(percent_decode("100%"), b"100%");
+        assert_eq!(percent_decode("%zz"), b"%zz");
+
+        let (bytes, content_type) = decode_data_url("data:,hello%20world").unwrap();
+        assert_eq!(&bytes[..], b"hello world");
+        assert_eq!(content_type, None);
+
+        // escaped padding inside base64
+        let (bytes, _) = decode_data_url("data:;base64,aGVsbG8%3D").unwrap();
+        assert_eq!(&bytes[..], b"hello");
+    }
+
+    #[test]
+    fn missing_comma()
+    {
+        assert!(decode_data_url("data:image/png;base64").is_err());
+        assert!(decode_data_url("image/png;base64,aGVsbG8=").is_err());
+    }
+}
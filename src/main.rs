@@ -1,24 +1,53 @@
-use std::{collections::HashMap, io::Cursor, sync::Arc};
+use std::{path::Path, sync::Arc};
 
-use anyhow::Error;
-use bytes::Bytes;
 use glam::Mat4;
 use glium::{
     backend::glutin::glutin::{
         dpi::{PhysicalSize, Size},
-        event::VirtualKeyCode,
         platform::macos::WindowBuilderExtMacOS,
     },
-    glutin::{event_loop::EventLoop, window::WindowBuilder},
+    glutin::{
+        event_loop::{EventLoop, EventLoopProxy},
+        window::WindowBuilder,
+    },
     Surface,
 };
 use nalgebra::Matrix4;
-use ux::{Grid, Renderers, TexturedVertex, TileRenderer, Vertex};
+use tokio::sync::mpsc;
+use ux::{
+    BackgroundWash, Grid, ProgressBar, Renderers, StatsOverlay, TexturedVertex, ThrottleIndicator,
+    TileRenderer, Vertex,
+};
 
-use crate::data::Set;
+use crate::{
+    cache::{CacherConfig, Pixels, Progress},
+    data::Set,
+    metrics::METRICS,
+    navigation::{Navigation, Viewport},
+    palette::Palette,
+    prefetch::{PrefetchConfig, Prefetcher},
+    screen::Router,
+    text::TextRenderer,
+    texture::{TextureCache, UploadBudget, UploadKind, UploadQueue},
+};
 
+pub mod animation;
+pub mod bundle;
 pub mod cache;
+pub mod cli;
 pub mod data;
+pub mod easing;
+pub mod loader;
+pub mod metrics;
+pub mod navigation;
+pub mod palette;
+pub mod prefetch;
+pub mod screen;
+pub mod store;
+pub mod text;
+pub mod texture;
+pub mod throttle;
+pub mod transform;
 pub mod ux;
 
 #[macro_use] extern crate glium;
@@ -32,6 +61,30 @@ extern crate anyhow;
 #[tokio::main]
 async fn main()
 {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.first().map(String::as_str)
+    {
+        Some("cache") => Some(cli::cache(&args[1..]).await),
+        Some("bundle") => Some(cli::bundle(&args[1..]).await),
+        Some("--bundle") => match args.get(1)
+        {
+            Some(path) => bundle::open(Path::new(path)).err().map(Err),
+            None => Some(Err(anyhow!("usage: lean-back --bundle <file>"))),
+        },
+        _ => None,
+    };
+    // subcommands exit when they are done, the UI only starts if there was none (or --bundle)
+    match result
+    {
+        Some(Ok(())) => return,
+        Some(Err(error)) =>
+        {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+        None => (),
+    }
+
     let event_loop = glium::glutin::event_loop::EventLoop::<Call>::with_user_event();
     let display = init_display(&event_loop);
     let texture_tile_renderer = TileRenderer::<TexturedVertex>::new(&display);
@@ -39,10 +92,23 @@ async fn main()
 
     let proxy = event_loop.create_proxy();
 
-    let context = Arc::new(Renderers::new(texture_tile_renderer, color_tile_renderer).await);
-    let mut grid = Grid::new();
+    let text_renderer = TextRenderer::new(&display);
+    let context =
+        Arc::new(Renderers::new(texture_tile_renderer, color_tile_renderer, text_renderer).await);
+    let grid = Grid::new(
+        Prefetcher::new(PrefetchConfig::from_env()),
+        Navigation::from_env(),
+    );
+    let cacher =
+        cache::create_cacher(Listener::EventLoop(proxy.clone()), CacherConfig::from_env()).await;
 
-    let mut texture_cache: HashMap<String, glium::texture::SrgbTexture2d> = HashMap::new();
+    let mut texture_cache = TextureCache::default();
+    let mut uploads = UploadQueue::new(UploadBudget::default());
+    let mut progress = Progress::default();
+    let mut screen_ready = false;
+    let started = tokio::time::Instant::now();
+    let mut background = BackgroundWash::default();
+    let mut router = Router::new(grid);
 
     event_loop.run(move |event, _, control_flow| {
         match event
@@ -63,37 +129,21 @@ async fn main()
                         Some(key) =>
                         {
                             let _vert_nudge = 1.0;
-                            match key
+                            let pressed =
+                                input.state == glium::glutin::event::ElementState::Pressed;
+                            router.key(key, pressed);
+                            if router.quit
                             {
-                                VirtualKeyCode::Escape =>
-                                {
-                                    *control_flow = glium::glutin::event_loop::ControlFlow::Exit;
-                                    return;
-                                }
-                                VirtualKeyCode::Up =>
-                                {
-                                    grid.up();
-                                }
-                                VirtualKeyCode::Down =>
-                                {
-                                    grid.down();
-                                }
-                                VirtualKeyCode::Left =>
-                                {
-                                    grid.left();
-                                }
-                                VirtualKeyCode::Right =>
-                                {
-                                    grid.right();
-                                }
-                                _ =>
-                                {
-                                    return;
-                                }
+                                *control_flow = glium::glutin::event_loop::ControlFlow::Exit;
+                                return;
                             }
                         }
                     }
                 }
+                glium::glutin::event::WindowEvent::ReceivedCharacter(character) =>
+                {
+                    router.character(character);
+                }
                 _ => return,
             },
             glium::glutin::event::Event::NewEvents(cause) => match cause
@@ -101,10 +151,19 @@ async fn main()
                 glium::glutin::event::StartCause::ResumeTimeReached { .. } => (),
                 glium::glutin::event::StartCause::Init =>
                 {
+                    tokio::spawn(async move {
+                        if let Err(error) = metrics::serve().await
+                        {
+                            eprintln!("could not serve cache metrics: {}", error);
+                        }
+                    });
                     let proxy = proxy.clone();
+                    let cacher = cacher.clone();
                     tokio::spawn(async move {
                         println!("Init!");
-                        data::fetch(proxy).await.unwrap_or_default();
+                        data::fetch(Listener::EventLoop(proxy), cacher)
+                            .await
+                            .unwrap_or_default();
                     });
                 }
                 _ => return,
@@ -112,50 +171,30 @@ async fn main()
 
             glium::glutin::event::Event::UserEvent(call) => match call
             {
-                Call::ToTexture { bytes, url } =>
+                Call::ToTexture { url, pixels } =>
                 {
-                    fn to_texture(
-                        bytes: Bytes,
-                        url: String,
-                        display: &glium::Display,
-                        texture_cache: &mut HashMap<String, glium::texture::SrgbTexture2d>,
-                    ) -> Result<(), Error>
-                    {
-                        let image =
-                            image::load(Cursor::new(bytes), image::ImageFormat::Jpeg)?.to_rgba8();
-                        let image_dimensions = image.dimensions();
-                        let image = glium::texture::RawImage2d::from_raw_rgba_reversed(
-                            &image.into_raw(),
-                            image_dimensions,
-                        );
-                        let texture = glium::texture::SrgbTexture2d::new(display, image)?;
-                        {
-                            texture_cache.insert(url, texture);
-                        }
-                        Ok(())
-                    }
-
-                    match to_texture(bytes, url.clone(), &display, &mut texture_cache)
-                    {
-                        Ok(_) =>
-                        {
-                            println!("Cached: {}", url);
-                            return;
-                        }
-                        Err(error) =>
-                        {
-                            println!("ToTexture: {} ERROR: {}", url, error.to_string());
-                            return;
-                        }
-                    }
+                    // uploads are deferred to the next frame so they can be spread out under the budget
+                    uploads.push(url, UploadKind::Art, pixels);
+                    return;
+                }
+                Call::Preview { url, pixels } =>
+                {
+                    uploads.push(url, UploadKind::Preview, pixels);
+                    return;
+                }
+                Call::Palette { url, palette } =>
+                {
+                    texture_cache.palettes.insert(url, palette);
+                    return;
                 }
-                Call::TextureCachingBatchComplete =>
+                Call::CacheProgress(update) =>
                 {
-                    println!("TextureCachingBatchComplete")
+                    progress = update;
+                    return;
                 }
                 Call::AddSet(set) =>
                 {
-                    grid.add(set);
+                    router.home.add(set);
                 }
             },
             _ => return,
@@ -165,8 +204,16 @@ async fn main()
             std::time::Instant::now() + std::time::Duration::from_nanos(16_666_667);
         *control_flow = glium::glutin::event_loop::ControlFlow::WaitUntil(next_frame_time);
 
+        cacher.prefetch(router.grid_mut().prefetch(&texture_cache));
+        uploads.drain(&display, router.grid(), &mut texture_cache);
+
         let mut frame = display.draw();
-        frame.clear_color_and_depth((0.129, 0.588, 0.953, 1.0), 1.0);
+        let focus = router
+            .grid()
+            .focus()
+            .and_then(|tile| texture_cache.palettes.get(&tile.item.image_url));
+        let wash = background.update(focus);
+        frame.clear_color_and_depth((wash.x, wash.y, wash.z, 1.0), 1.0);
 
         let (width, height) = frame.get_dimensions();
         let aspect_ratio = height as f32 / width as f32;
@@ -178,14 +225,50 @@ async fn main()
             [0.0, 0.0, 0.0, 1.0],
         ]);
 
+        let size = 5.0;
+        router.set_viewport(Viewport::from_screen(size, aspect_ratio));
         let matrix = {
-            let size = 5.0;
             let matrix: Matrix4<f32> = Matrix4::new_orthographic(0.0, size, size, 0.0, -10.0, 10.0);
             Mat4::from_cols_array_2d(&matrix.data.0)
         };
 
         let ortho = matrix * aspect_matrix;
-        grid.draw(&mut frame, ortho, context.clone(), &mut texture_cache);
+        router.draw(&mut frame, ortho, context.clone(), &texture_cache);
+
+        let screen_progress = router.home.screen_progress(&texture_cache);
+        if !screen_ready && screen_progress >= 1.0
+        {
+            screen_ready = true;
+            println!(
+                "First screenful ready: {}/{} images, {} failed, {} bytes",
+                progress.completed, progress.queued, progress.failed, progress.bytes
+            );
+        }
+
+        if METRICS.is_throttled()
+        {
+            ThrottleIndicator::draw(&mut frame, context.clone(), started);
+        }
+
+        if router.preferences.show_stats
+        {
+            StatsOverlay::draw(&mut frame, context.clone(), &METRICS);
+        }
+
+        // the feed hasn't arrived or the cacher is still working: show how far along we are
+        if router.home.rows.is_empty() || !progress.is_done()
+        {
+            // the bar fills up with the first screenful of art, then follows the rest of the queue
+            let fraction = if screen_ready
+            {
+                progress.fraction()
+            }
+            else
+            {
+                screen_progress
+            };
+            ProgressBar::draw(&mut frame, context.clone(), fraction);
+        }
 
         frame.finish().unwrap();
     });
@@ -209,9 +292,43 @@ pub enum Call
 {
     ToTexture
     {
-        bytes: Bytes,
-        url:   String,
+        url:    String,
+        pixels: Pixels,
+    },
+    // a tiny blurred version of the art to show while the real thing is on its way
+    Preview
+    {
+        url:    String,
+        pixels: Pixels,
     },
-    TextureCachingBatchComplete,
+    // the art's colors, which can arrive before any of its pixels
+    Palette
+    {
+        url:     String,
+        palette: Palette,
+    },
+    CacheProgress(Progress),
     AddSet(Set),
 }
+
+// whoever is interested in what the data fetcher and the cacher are up to: the event loop
+// while the UI is running, or a plain channel for the headless cache commands
+#[derive(Clone)]
+pub enum Listener
+{
+    EventLoop(EventLoopProxy<Call>),
+    Channel(mpsc::UnboundedSender<Call>),
+}
+
+impl Listener
+{
+    // a listener that has gone away is not an error worth reporting, there's no one left to tell
+    pub fn send_event(&self, call: Call)
+    {
+        match self
+        {
+            Listener::EventLoop(proxy) => proxy.send_event(call).unwrap_or_default(),
+            Listener::Channel(tx) => tx.send(call).unwrap_or_default(),
+        }
+    }
+}
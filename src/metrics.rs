@@ -0,0 +1,240 @@
+use std::{
+    fmt::Write as _,
+    sync::atomic::{AtomicI64, AtomicU64, Ordering},
+    time::Duration,
+};
+
+use anyhow::Error;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+
+lazy_static! {
+    pub static ref METRICS: Metrics = Metrics::default();
+}
+
+// counters for everything the cacher does.  They are global so the cacher, the event loop
+// and the stats endpoint can all reach them without threading handles around.  There is no
+// eviction counter: uploaded textures are never evicted, and the disk cache is only trimmed
+// by `lean-back cache purge`, which reports what it removed itself
+#[derive(Default)]
+pub struct Metrics
+{
+    // tiles drawn with their art already uploaded to the GPU, and tiles still waiting for it
+    pub memory_hits:      AtomicU64,
+    pub memory_misses:    AtomicU64,
+    // a requested url that was already queued, downloading or uploaded, so it was folded into
+    // the earlier request instead of being queued again
+    pub coalesced:        AtomicU64,
+    // a requested url that was new to the cacher and went into the queue
+    pub admitted:         AtomicU64,
+    // urls queued ahead of everything else because the user is heading their way
+    pub prefetches:       AtomicU64,
+    pub disk_hits:        AtomicU64,
+    pub disk_misses:      AtomicU64,
+    pub downloads:        AtomicU64,
+    pub download_errors:  AtomicU64,
+    pub decode_errors:    AtomicU64,
+    pub bytes_downloaded: AtomicU64,
+    pub bytes_from_disk:  AtomicU64,
+    // urls waiting for a free download worker
+    pub queue_depth:      AtomicI64,
+    // downloads held back by the bandwidth cap right now
+    pub throttled:        AtomicI64,
+    // disk entries thrown away because they could not be decoded
+    pub corrupt_removed:  AtomicU64,
+    pub download_latency: Histogram,
+    pub decode_time:      Histogram,
+}
+
+impl Metrics
+{
+    pub fn increment(counter: &AtomicU64) { Self::add(counter, 1); }
+
+    pub fn add(counter: &AtomicU64, value: u64) { counter.fetch_add(value, Ordering::Relaxed); }
+
+    pub fn get(counter: &AtomicU64) -> u64 { counter.load(Ordering::Relaxed) }
+
+    pub fn memory_hit_rate(&self) -> f32
+    {
+        rate(Self::get(&self.memory_hits), Self::get(&self.memory_misses))
+    }
+
+    // how many of the requests were for urls the cacher already had in hand
+    pub fn coalesced_rate(&self) -> f32
+    {
+        rate(Self::get(&self.coalesced), Self::get(&self.admitted))
+    }
+
+    pub fn disk_hit_rate(&self) -> f32
+    {
+        rate(Self::get(&self.disk_hits), Self::get(&self.disk_misses))
+    }
+
+    pub fn queue_depth(&self) -> i64 { self.queue_depth.load(Ordering::Relaxed) }
+
+    pub fn is_throttled(&self) -> bool { self.throttled.load(Ordering::Relaxed) > 0 }
+
+    // the Prometheus text exposition format
+    pub fn render(&self) -> String
+    {
+        let mut out = String::new();
+        let counters = [
+            ("memory_hits", &self.memory_hits),
+            ("memory_misses", &self.memory_misses),
+            ("coalesced", &self.coalesced),
+            ("admitted", &self.admitted),
+            ("prefetches", &self.prefetches),
+            ("disk_hits", &self.disk_hits),
+            ("disk_misses", &self.disk_misses),
+            ("downloads", &self.downloads),
+            ("download_errors", &self.download_errors),
+            ("decode_errors", &self.decode_errors),
+            ("bytes_downloaded", &self.bytes_downloaded),
+            ("bytes_from_disk", &self.bytes_from_disk),
+            ("corrupt_removed", &self.corrupt_removed),
+        ];
+        for (name, counter) in counters
+        {
+            writeln!(out, "# TYPE lean_back_cache_{}_total counter", name).unwrap();
+            writeln!(out, "lean_back_cache_{}_total {}", name, Self::get(counter)).unwrap();
+        }
+        writeln!(out, "# TYPE lean_back_cache_queue_depth gauge").unwrap();
+        writeln!(out, "lean_back_cache_queue_depth {}", self.queue_depth()).unwrap();
+        writeln!(out, "# TYPE lean_back_cache_throttled gauge").unwrap();
+        writeln!(
+            out,
+            "lean_back_cache_throttled {}",
+            self.throttled.load(Ordering::Relaxed)
+        )
+        .unwrap();
+        self.download_latency
+            .render(&mut out, "lean_back_cache_download_seconds");
+        self.decode_time
+            .render(&mut out, "lean_back_cache_decode_seconds");
+        out
+    }
+}
+
+fn rate(hits: u64, misses: u64) -> f32
+{
+    if hits + misses == 0
+    {
+        return 0.0;
+    }
+    hits as f32 / (hits + misses) as f32
+}
+
+// a fixed bucket histogram of durations
+pub struct Histogram
+{
+    buckets: Vec<AtomicU64>,
+    count:   AtomicU64,
+    // in microseconds
+    sum:     AtomicU64,
+}
+
+impl Histogram
+{
+    // upper bounds in milliseconds, anything slower lands in the implicit +Inf bucket
+    const BOUNDS: [u64; 10] = [5, 10, 25, 50, 100, 250, 500, 1000, 2500, 5000];
+
+    pub fn record(&self, duration: Duration)
+    {
+        let millis = duration.as_millis() as u64;
+        for (bound, bucket) in Self::BOUNDS.iter().zip(&self.buckets)
+        {
+            if millis <= *bound
+            {
+                bucket.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub fn mean(&self) -> Duration
+    {
+        let count = self.count.load(Ordering::Relaxed);
+        if count == 0
+        {
+            return Duration::ZERO;
+        }
+        Duration::from_micros(self.sum.load(Ordering::Relaxed) / count)
+    }
+
+    // buckets are stored cumulatively, which is what prometheus expects
+    fn render(&self, out: &mut String, name: &str)
+    {
+        writeln!(out, "# TYPE {} histogram", name).unwrap();
+        for (bound, bucket) in Self::BOUNDS.iter().zip(&self.buckets)
+        {
+            writeln!(
+                out,
+                "{}_bucket{{le=\"{}\"}} {}",
+                name,
+                *bound as f64 / 1000.0,
+                bucket.load(Ordering::Relaxed)
+            )
+            .unwrap();
+        }
+        let count = self.count.load(Ordering::Relaxed);
+        writeln!(out, "{}_bucket{{le=\"+Inf\"}} {}", name, count).unwrap();
+        writeln!(
+            out,
+            "{}_sum {}",
+            name,
+            self.sum.load(Ordering::Relaxed) as f64 / 1_000_000.0
+        )
+        .unwrap();
+        writeln!(out, "{}_count {}", name, count).unwrap();
+    }
+}
+
+impl Default for Histogram
+{
+    fn default() -> Self
+    {
+        Self {
+            buckets: Histogram::BOUNDS
+                .iter()
+                .map(|_| AtomicU64::new(0))
+                .collect(),
+            count:   AtomicU64::new(0),
+            sum:     AtomicU64::new(0),
+        }
+    }
+}
+
+// serves the metrics in the prometheus format to anyone that connects to localhost on
+// LEAN_BACK_METRICS_PORT (default 9898), whatever path they ask for
+pub async fn serve() -> Result<(), Error>
+{
+    let port = std::env::var("LEAN_BACK_METRICS_PORT")
+        .ok()
+        .and_then(|port| port.parse::<u16>().ok())
+        .unwrap_or(9898);
+    let listener = TcpListener::bind(("127.0.0.1", port)).await?;
+    println!("Serving cache metrics on http://127.0.0.1:{}/metrics", port);
+    loop
+    {
+        let (mut socket, _) = listener.accept().await?;
+        tokio::spawn(async move {
+            // we don't care what was asked for, but the request has to be read before replying
+            let mut request = [0u8; 1024];
+            socket.read(&mut request).await.unwrap_or_default();
+            let body = METRICS.render();
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            socket
+                .write_all(response.as_bytes())
+                .await
+                .unwrap_or_default();
+        });
+    }
+}
@@ -0,0 +1,255 @@
+use std::ops::Range;
+
+use crate::ux::Grid;
+
+// how much of the grid fits on screen, in tiles across and rows down.  Tiles can be cut off
+// by the edge of the screen so these are rarely whole numbers
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Viewport
+{
+    pub columns: f32,
+    pub rows:    f32,
+}
+
+impl Viewport
+{
+    // the projection is `size` units tall and rows are Grid::ROW_HEIGHT.  Tiles are 1.78 units
+    // wide (Row's hard coded aspect fix), aspect_ratio is height over width like in main
+    pub fn from_screen(size: f32, aspect_ratio: f32) -> Self
+    {
+        Self {
+            columns: size / aspect_ratio / 1.78,
+            rows:    size / Grid::ROW_HEIGHT,
+        }
+    }
+}
+
+// a 16:9 window, until the first frame says otherwise
+impl Default for Viewport
+{
+    fn default() -> Self { Self::from_screen(5.0, 9.0 / 16.0) }
+}
+
+// the room kept between the selected tile and each edge of the screen, in tiles (left and
+// right) and rows (top and bottom).  The selected tile grows and glows so it needs a little
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SafeArea
+{
+    pub left:   f32,
+    pub right:  f32,
+    pub top:    f32,
+    pub bottom: f32,
+}
+
+impl Default for SafeArea
+{
+    fn default() -> Self
+    {
+        Self {
+            left:   0.2,
+            right:  0.2,
+            top:    0.2,
+            bottom: 0.2,
+        }
+    }
+}
+
+impl SafeArea
+{
+    // LEAN_BACK_SAFE_AREA is either one margin for every edge ("0.5") or one for each,
+    // "left,right,top,bottom".  Anything else keeps the default
+    pub fn from_env() -> Self
+    {
+        std::env::var("LEAN_BACK_SAFE_AREA")
+            .ok()
+            .and_then(|value| Self::parse(&value))
+            .unwrap_or_default()
+    }
+
+    fn parse(value: &str) -> Option<Self>
+    {
+        let margins = value
+            .split(',')
+            .map(|margin| {
+                margin
+                    .trim()
+                    .parse::<f32>()
+                    .ok()
+                    .filter(|margin| margin.is_finite() && *margin >= 0.0)
+            })
+            .collect::<Option<Vec<f32>>>()?;
+        match margins[..]
+        {
+            [all] => Some(Self {
+                left:   all,
+                right:  all,
+                top:    all,
+                bottom: all,
+            }),
+            [left, right, top, bottom] => Some(Self {
+                left,
+                right,
+                top,
+                bottom,
+            }),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Navigation
+{
+    // the grid and rows scroll on every move so the selection always sits in the upper left
+    Pinned,
+    // the grid and rows only scroll when the selection would leave the safe area
+    Viewport(SafeArea),
+}
+
+impl Navigation
+{
+    // LEAN_BACK_NAVIGATION=pinned brings back the corner pinned navigation
+    pub fn from_env() -> Self
+    {
+        match std::env::var("LEAN_BACK_NAVIGATION").as_deref()
+        {
+            Ok("pinned") => Navigation::Pinned,
+            _ => Navigation::Viewport(SafeArea::from_env()),
+        }
+    }
+
+    // where the row should scroll to (in tiles) so tile `index` of `count` is in view
+    pub fn scroll_columns(&self, scroll: f32, index: usize, count: usize, visible: f32) -> f32
+    {
+        match self
+        {
+            Navigation::Pinned => index as f32,
+            Navigation::Viewport(safe) =>
+            {
+                scroll_into_view(scroll, index, count, visible, safe.left, safe.right)
+            }
+        }
+    }
+
+    // where the grid should scroll to (in rows) so row `index` of `count` is in view
+    pub fn scroll_rows(&self, scroll: f32, index: usize, count: usize, visible: f32) -> f32
+    {
+        match self
+        {
+            Navigation::Pinned => index as f32,
+            Navigation::Viewport(safe) =>
+            {
+                scroll_into_view(scroll, index, count, visible, safe.top, safe.bottom)
+            }
+        }
+    }
+}
+
+// the smallest scroll that puts [index, index + 1] inside [scroll + before, scroll + visible - after].
+// Nothing moves while the item is already inside.  The scroll never runs past either end of
+// the list, so the first and last items sit flush against the edge instead of the margin.
+// If the safe area is too small for a whole item the leading edge wins
+fn scroll_into_view(
+    scroll: f32,
+    index: usize,
+    count: usize,
+    visible: f32,
+    before: f32,
+    after: f32,
+) -> f32
+{
+    let start = index as f32;
+    let end = start + 1.0;
+    let mut scroll = scroll;
+    if end > scroll + visible - after
+    {
+        scroll = end - visible + after;
+    }
+    if start < scroll + before
+    {
+        scroll = start - before;
+    }
+    let last = (count as f32 - visible).max(0.0);
+    scroll.clamp(0.0, last)
+}
+
+// the indices of every item at least partly on screen, including the ones straddling an edge
+pub fn visible(scroll: f32, visible: f32, count: usize) -> Range<usize>
+{
+    let first = scroll.max(0.0).floor() as usize;
+    let last = ((scroll + visible).ceil().max(0.0) as usize).min(count);
+    first.min(last)..last
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    fn assert_close(actual: f32, expected: f32)
+    {
+        assert!(
+            (actual - expected).abs() < 1e-5,
+            "{} is not {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn stays_put_while_inside() { assert_close(scroll_into_view(1.0, 2, 10, 4.5, 0.2, 0.2), 1.0); }
+
+    #[test]
+    fn straddling_an_edge()
+    {
+        // tile 4 is half cut off by the trailing edge, it comes in with the margin after it
+        assert_close(scroll_into_view(0.0, 4, 10, 4.5, 0.2, 0.2), 0.7);
+        // tile 2 is half cut off by the leading edge
+        assert_close(scroll_into_view(2.5, 2, 10, 4.5, 0.2, 0.2), 1.8);
+    }
+
+    #[test]
+    fn safe_area_smaller_than_a_tile()
+    {
+        // 1.2 tiles on screen less 0.4 of margins leaves no room for a whole tile, the leading
+        // edge wins
+        assert_close(scroll_into_view(0.0, 3, 10, 1.2, 0.2, 0.2), 2.8);
+        assert_close(scroll_into_view(8.0, 3, 10, 1.2, 0.2, 0.2), 2.8);
+    }
+
+    #[test]
+    fn clamped_at_both_ends()
+    {
+        // the first and last tiles sit flush against the edge instead of the margin
+        assert_close(scroll_into_view(5.0, 0, 10, 4.5, 0.2, 0.2), 0.0);
+        assert_close(scroll_into_view(0.0, 9, 10, 4.5, 0.2, 0.2), 5.5);
+        // a list shorter than the screen never scrolls
+        assert_close(scroll_into_view(0.0, 2, 3, 4.5, 0.2, 0.2), 0.0);
+    }
+
+    #[test]
+    fn safe_area_from_the_environment()
+    {
+        assert_eq!(
+            SafeArea::parse("0.5"),
+            Some(SafeArea {
+                left:   0.5,
+                right:  0.5,
+                top:    0.5,
+                bottom: 0.5,
+            })
+        );
+        assert_eq!(
+            SafeArea::parse("0.1, 0.2,0.3,0"),
+            Some(SafeArea {
+                left:   0.1,
+                right:  0.2,
+                top:    0.3,
+                bottom: 0.0,
+            })
+        );
+        assert_eq!(SafeArea::parse("0.1,0.2"), None);
+        assert_eq!(SafeArea::parse("-1"), None);
+        assert_eq!(SafeArea::parse("wide"), None);
+    }
+}
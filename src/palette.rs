@@ -0,0 +1,99 @@
+use glam::Vec3;
+use image::{DynamicImage, GenericImageView};
+
+// the colors of a piece of art, worked out once when it is decoded and kept in the disk cache
+// so a tile can be tinted before any of its pixels arrive
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Palette
+{
+    // the mean of every pixel, good for washes and placeholders since it is never garish
+    pub average:  [u8; 3],
+    // the most common color, favouring saturated ones, good for accents like the focus glow
+    pub dominant: [u8; 3],
+}
+
+impl Palette
+{
+    // the art is boiled down to a small thumbnail first, the palette doesn't need detail
+    const SAMPLE: u32 = 32;
+
+    pub fn extract(image: &DynamicImage) -> Self
+    {
+        let thumbnail = image.thumbnail(Self::SAMPLE, Self::SAMPLE);
+        let mut sum = [0u64; 3];
+        let mut count = 0u64;
+        // 3 bits per channel, each bucket keeps its weight and the sum of its colors
+        let mut buckets = vec![(0f32, [0f32; 3]); 512];
+        for (_, _, pixel) in thumbnail.pixels()
+        {
+            let [r, g, b, a] = pixel.0;
+            if a < 128
+            {
+                continue;
+            }
+            for (total, channel) in sum.iter_mut().zip([r, g, b])
+            {
+                *total += channel as u64;
+            }
+            count += 1;
+
+            // grey pixels still count, but vivid ones count for more
+            let chroma = (r.max(g).max(b) - r.min(g).min(b)) as f32 / 255.0;
+            let weight = 1.0 + chroma * 3.0;
+            let index = ((r >> 5) as usize) << 6 | ((g >> 5) as usize) << 3 | (b >> 5) as usize;
+            let bucket = &mut buckets[index];
+            bucket.0 += weight;
+            for (total, channel) in bucket.1.iter_mut().zip([r, g, b])
+            {
+                *total += channel as f32 * weight;
+            }
+        }
+        if count == 0
+        {
+            return Self::default();
+        }
+
+        let average = sum.map(|total| (total / count) as u8);
+        let dominant = buckets
+            .iter()
+            .max_by(|a, b| a.0.total_cmp(&b.0))
+            .map(|(weight, total)| total.map(|channel| (channel / weight) as u8))
+            .unwrap_or(average);
+        Self { average, dominant }
+    }
+
+    // six bytes on disk, average then dominant
+    pub fn to_bytes(&self) -> Vec<u8> { [self.average, self.dominant].concat() }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self>
+    {
+        match bytes
+        {
+            [ar, ag, ab, dr, dg, db] => Some(Self {
+                average:  [*ar, *ag, *ab],
+                dominant: [*dr, *dg, *db],
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn average(&self) -> Vec3 { to_vec3(self.average) }
+
+    pub fn dominant(&self) -> Vec3 { to_vec3(self.dominant) }
+}
+
+impl Default for Palette
+{
+    fn default() -> Self
+    {
+        Self {
+            average:  [255, 255, 255],
+            dominant: [255, 255, 255],
+        }
+    }
+}
+
+fn to_vec3(color: [u8; 3]) -> Vec3
+{
+    Vec3::new(color[0] as f32, color[1] as f32, color[2] as f32) / 255.0
+}
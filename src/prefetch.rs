@@ -0,0 +1,286 @@
+use std::{
+    collections::HashMap,
+    ops::Range,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    cache::{env_flag, env_limit, CacherConfig},
+    texture::TextureCache,
+    ux::Row,
+};
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Direction
+{
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+#[derive(Clone, Debug)]
+pub struct PrefetchConfig
+{
+    // how many tiles (or rows) past the edge of the screen to warm in the direction of travel,
+    // this grows with the speed of travel
+    pub ahead:     usize,
+    // how many off screen rows above and below the selection to warm while moving sideways
+    pub around:    usize,
+    // bytes per second the prefetcher may ask the cacher to download
+    pub bandwidth: usize,
+    // decoded bytes that may be on their way to the GPU because of prefetching
+    pub memory:    usize,
+    // the widest the cacher decodes art, which sets how much memory each texture takes
+    pub max_width: u32,
+}
+
+impl PrefetchConfig
+{
+    // reads LEAN_BACK_PREFETCH_BANDWIDTH and LEAN_BACK_PREFETCH_MEMORY (both in bytes), and the
+    // max width from the cacher's config.  LEAN_BACK_DATA_SAVER turns prefetching off, nothing
+    // is downloaded until it is on screen
+    pub fn from_env() -> Self
+    {
+        let default = Self {
+            max_width: CacherConfig::from_env().max_width,
+            ..Self::default()
+        };
+        if env_flag("LEAN_BACK_DATA_SAVER")
+        {
+            return Self {
+                ahead: 0,
+                around: 0,
+                ..default
+            };
+        }
+        Self {
+            bandwidth: env_limit("LEAN_BACK_PREFETCH_BANDWIDTH").unwrap_or(default.bandwidth),
+            memory: env_limit("LEAN_BACK_PREFETCH_MEMORY").unwrap_or(default.memory),
+            ..default
+        }
+    }
+}
+
+impl Default for PrefetchConfig
+{
+    fn default() -> Self
+    {
+        Self {
+            ahead:     4,
+            around:    1,
+            bandwidth: 2 * 1024 * 1024,
+            memory:    64 * 1024 * 1024,
+            max_width: CacherConfig::default().max_width,
+        }
+    }
+}
+
+// watches how the user navigates the grid and warms the images they are heading towards.
+// Budgets are enforced with estimates since the prefetcher only ever sees urls, never bytes
+pub struct Prefetcher
+{
+    pub config: PrefetchConfig,
+    direction:  Option<Direction>,
+    last_move:  Option<Instant>,
+    // moves per second in the current direction, smoothed
+    velocity:   f32,
+    // prefetched urls that have not been uploaded yet, and when they were asked for
+    in_flight:  HashMap<String, Instant>,
+    // how many more urls the bandwidth budget allows right now
+    allowance:  f32,
+    refilled:   Instant,
+}
+
+impl Prefetcher
+{
+    // a typical jpeg tile as it comes over the wire
+    const ESTIMATED_DOWNLOAD: usize = 150 * 1024;
+    // after this long without a move the user is considered to be at rest
+    const IDLE: Duration = Duration::from_secs(1);
+    // in flight urls older than this have most likely failed, stop counting them
+    const STALE: Duration = Duration::from_secs(30);
+
+    pub fn new(config: PrefetchConfig) -> Self
+    {
+        Self {
+            config,
+            direction: None,
+            last_move: None,
+            velocity: 0.0,
+            in_flight: HashMap::new(),
+            allowance: 0.0,
+            refilled: Instant::now(),
+        }
+    }
+
+    pub fn record(&mut self, direction: Direction)
+    {
+        let now = Instant::now();
+        let rate = match self.last_move
+        {
+            Some(last) => 1.0 / (now - last).as_secs_f32().max(0.01),
+            None => 0.0,
+        };
+        // changing direction throws away the momentum
+        self.velocity = if self.direction == Some(direction)
+        {
+            self.velocity * 0.5 + rate * 0.5
+        }
+        else
+        {
+            0.0
+        };
+        self.direction = Some(direction);
+        self.last_move = Some(now);
+    }
+
+    pub fn velocity(&self) -> f32
+    {
+        match self.last_move
+        {
+            Some(last) if last.elapsed() < Self::IDLE => self.velocity,
+            _ => 0.0,
+        }
+    }
+
+    // the urls to warm right now, most urgent first.  `visible` is the range of rows on screen
+    // and `columns` how many tiles fit across
+    pub fn plan(
+        &mut self,
+        rows: &[Row],
+        selection: usize,
+        visible: Range<usize>,
+        columns: f32,
+        texture_cache: &TextureCache,
+    ) -> Vec<String>
+    {
+        self.refill();
+        self.in_flight.retain(|url, asked| {
+            !texture_cache.art.contains_key(url) && asked.elapsed() < Self::STALE
+        });
+
+        let direction = match self.direction
+        {
+            Some(direction) => direction,
+            None => return vec![],
+        };
+        // every move per second of speed looks one more tile ahead, up to triple the base reach
+        let reach = (self.config.ahead + self.velocity() as usize).min(self.config.ahead * 3);
+
+        let mut candidates: Vec<&str> = vec![];
+        match direction
+        {
+            Direction::Down =>
+            {
+                for index in visible.end..visible.end + reach
+                {
+                    candidates.extend(on_screen(rows, index, columns));
+                }
+            }
+            Direction::Up =>
+            {
+                for index in (visible.start.saturating_sub(reach)..visible.start).rev()
+                {
+                    candidates.extend(on_screen(rows, index, columns));
+                }
+            }
+            Direction::Left | Direction::Right =>
+            {
+                if let Some(row) = rows.get(selection)
+                {
+                    let on_screen = row.visible(columns);
+                    let tiles = match direction
+                    {
+                        Direction::Right => on_screen.end..on_screen.end + reach,
+                        _ => on_screen.start.saturating_sub(reach)..on_screen.start,
+                    };
+                    let mut ahead: Vec<&str> = tiles
+                        .filter_map(|index| row.tiles.get(index))
+                        .map(|tile| tile.item.image_url.as_str())
+                        .collect();
+                    if direction == Direction::Left
+                    {
+                        ahead.reverse();
+                    }
+                    candidates.extend(ahead);
+                }
+                for distance in 1..=self.config.around
+                {
+                    if let Some(index) = visible.start.checked_sub(distance)
+                    {
+                        candidates.extend(on_screen(rows, index, columns));
+                    }
+                    candidates.extend(on_screen(rows, visible.end + distance - 1, columns));
+                }
+            }
+        }
+
+        let capacity =
+            (self.config.memory / self.estimated_texture()).saturating_sub(self.in_flight.len());
+        let mut urls = vec![];
+        for url in candidates
+        {
+            if urls.len() >= capacity || self.allowance < 1.0
+            {
+                break;
+            }
+            if texture_cache.art.contains_key(url) || self.in_flight.contains_key(url)
+            {
+                continue;
+            }
+            self.in_flight.insert(url.to_string(), Instant::now());
+            self.allowance -= 1.0;
+            urls.push(url.to_string());
+        }
+        urls
+    }
+
+    // a 16:9 tile decoded at the cacher's max_width, plus a third again for its mipmaps
+    fn estimated_texture(&self) -> usize
+    {
+        let width = self.config.max_width as usize;
+        (width * (width * 9 / 16) * 4 * 4 / 3).max(1)
+    }
+
+    // a token bucket holding at most one second's worth of downloads
+    fn refill(&mut self)
+    {
+        let per_second = self.config.bandwidth as f32 / Self::ESTIMATED_DOWNLOAD as f32;
+        let elapsed = self.refilled.elapsed().as_secs_f32();
+        self.allowance = (self.allowance + elapsed * per_second).min(per_second.max(1.0));
+        self.refilled = Instant::now();
+    }
+}
+
+// the urls of the tiles that would be on screen if this row was scrolled into view
+fn on_screen(rows: &[Row], index: usize, columns: f32) -> Vec<&str>
+{
+    match rows.get(index)
+    {
+        Some(row) => row.tiles[row.visible(columns)]
+            .iter()
+            .map(|tile| tile.item.image_url.as_str())
+            .collect(),
+        None => vec![],
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[test]
+    fn texture_estimate_follows_max_width()
+    {
+        let full = Prefetcher::new(PrefetchConfig::default());
+        assert_eq!(full.estimated_texture(), 640 * 360 * 4 * 4 / 3);
+        // data saver art is half as wide, so a quarter of the memory
+        let saver = Prefetcher::new(PrefetchConfig {
+            max_width: CacherConfig::DATA_SAVER_WIDTH,
+            ..PrefetchConfig::default()
+        });
+        assert_eq!(saver.estimated_texture() * 4, full.estimated_texture());
+    }
+}
@@ -0,0 +1,776 @@
+use std::{collections::HashSet, sync::Arc, time::Duration};
+
+use glam::{Mat4, Vec3, Vec4};
+use glium::{glutin::event::VirtualKeyCode, Frame};
+
+use crate::{
+    animation::Animation,
+    data::{Item, Set},
+    easing::Easing,
+    navigation::{Navigation, SafeArea, Viewport},
+    prefetch::{PrefetchConfig, Prefetcher},
+    texture::TextureCache,
+    ux::{clamp, lerp, Grid, Renderers},
+};
+
+// the home grid sits at the bottom of a stack of screens.  Keys go to the screen on top, and
+// Back pops it, except at home where it asks before quitting
+pub struct Router
+{
+    pub home:        Grid,
+    pub preferences: Preferences,
+    // set once the user has confirmed they want to leave
+    pub quit:        bool,
+    stack:           Vec<Screen>,
+}
+
+// what the settings screen changes
+pub struct Preferences
+{
+    pub show_stats: bool,
+    pub navigation: Navigation,
+    // the margins viewport navigation scrolls by, kept from startup while pinned navigation
+    // is switched on so switching back restores them
+    pub safe_area:  SafeArea,
+}
+
+// what a screen wants the router to do after a key
+pub enum Route
+{
+    Stay,
+    Push(Box<Screen>),
+    Back,
+    Quit,
+}
+
+pub enum Screen
+{
+    Detail(Detail),
+    // every item of one set, wrapped over as many rows as it takes
+    Set(Grid),
+    Search(Search),
+    Settings(Settings),
+    // the question asked before Back leaves the app
+    Quit,
+}
+
+impl Router
+{
+    pub fn new(home: Grid) -> Self
+    {
+        let safe_area = match home.navigation
+        {
+            Navigation::Viewport(safe_area) => safe_area,
+            Navigation::Pinned => SafeArea::from_env(),
+        };
+        let preferences = Preferences {
+            show_stats: false,
+            navigation: home.navigation,
+            safe_area,
+        };
+        Self {
+            home,
+            preferences,
+            quit: false,
+            stack: vec![],
+        }
+    }
+
+    pub fn key(&mut self, key: VirtualKeyCode, pressed: bool)
+    {
+        // winit reports both the press and the release, everything acts on the press.  Held
+        // keys repeat their press
+        if !pressed
+        {
+            return;
+        }
+        if key == VirtualKeyCode::F3
+        {
+            self.preferences.show_stats = !self.preferences.show_stats;
+            return;
+        }
+
+        let columns = self.home.viewport.columns.floor().max(1.0) as usize;
+        let navigation = self.preferences.navigation;
+        let route = match self.stack.last_mut()
+        {
+            None => home_key(&mut self.home, key, columns, navigation),
+            Some(screen) => screen.key(key, &mut self.preferences, &mut self.home),
+        };
+        match route
+        {
+            Route::Stay => (),
+            Route::Push(screen) => self.stack.push(*screen),
+            Route::Back =>
+            {
+                if self.stack.pop().is_none()
+                {
+                    self.stack.push(Screen::Quit);
+                }
+            }
+            Route::Quit => self.quit = true,
+        }
+        self.apply_preferences();
+    }
+
+    // typed text only matters to search
+    pub fn character(&mut self, character: char)
+    {
+        if let Some(Screen::Search(search)) = self.stack.last_mut()
+        {
+            let columns = self.home.viewport.columns.floor().max(1.0) as usize;
+            search.type_character(character, columns, self.preferences.navigation);
+        }
+    }
+
+    // the grid the user is looking at, which decides what gets prefetched and uploaded first
+    pub fn grid(&self) -> &Grid
+    {
+        self.stack
+            .iter()
+            .rev()
+            .find_map(Screen::grid)
+            .unwrap_or(&self.home)
+    }
+
+    pub fn grid_mut(&mut self) -> &mut Grid
+    {
+        match self.stack.iter_mut().rev().find_map(Screen::grid_mut)
+        {
+            Some(grid) => grid,
+            None => &mut self.home,
+        }
+    }
+
+    pub fn set_viewport(&mut self, viewport: Viewport)
+    {
+        self.home.viewport = viewport;
+        for grid in self.stack.iter_mut().filter_map(Screen::grid_mut)
+        {
+            grid.viewport = viewport;
+        }
+    }
+
+    pub fn draw(
+        &mut self,
+        frame: &mut Frame,
+        projection: Mat4,
+        context: Arc<Renderers>,
+        texture_cache: &TextureCache,
+    )
+    {
+        let width = self.home.viewport.columns * 1.78;
+        // full screen grids hide everything underneath them
+        let bottom = self.stack.iter().rposition(Screen::is_opaque);
+        if bottom.is_none()
+        {
+            self.home
+                .draw(frame, projection, context.clone(), texture_cache);
+        }
+        for index in bottom.unwrap_or(0)..self.stack.len()
+        {
+            match &self.stack[index]
+            {
+                Screen::Detail(detail) =>
+                {
+                    // it grows out of the focused tile of the screen below, or straight from
+                    // the full screen if that has nothing focused
+                    let below = match index
+                    {
+                        0 => self.home.focus_matrix(projection),
+                        _ => self.stack[index - 1].focus_matrix(projection),
+                    };
+                    let tile = below.unwrap_or(projection);
+                    detail.draw(
+                        frame,
+                        projection,
+                        tile,
+                        width,
+                        context.clone(),
+                        texture_cache,
+                    );
+                }
+                screen => screen.draw(
+                    frame,
+                    projection,
+                    width,
+                    context.clone(),
+                    texture_cache,
+                    &self.preferences,
+                ),
+            }
+        }
+
+        // the detail view leaves the stack once it has shrunk back into its tile
+        if let Some(Screen::Detail(detail)) = self.stack.last()
+        {
+            if detail.is_closed()
+            {
+                self.stack.pop();
+            }
+        }
+    }
+
+    fn apply_preferences(&mut self)
+    {
+        let navigation = self.preferences.navigation;
+        self.home.navigation = navigation;
+        for grid in self.stack.iter_mut().filter_map(Screen::grid_mut)
+        {
+            grid.navigation = navigation;
+        }
+    }
+}
+
+fn home_key(home: &mut Grid, key: VirtualKeyCode, columns: usize, navigation: Navigation) -> Route
+{
+    match key
+    {
+        VirtualKeyCode::Escape | VirtualKeyCode::Back => Route::Back,
+        VirtualKeyCode::Space => match home.rows.get(home.selection)
+        {
+            Some(row) => Route::Push(Box::new(Screen::Set(tiled(&row.set, columns, navigation)))),
+            None => Route::Stay,
+        },
+        VirtualKeyCode::Slash => Route::Push(Box::new(Screen::Search(Search::new(home)))),
+        VirtualKeyCode::Comma => Route::Push(Box::new(Screen::Settings(Settings::default()))),
+        _ =>
+        {
+            move_grid(home, key);
+            grid_key(home, key)
+        }
+    }
+}
+
+// arrows move the selection and Enter opens the detail view, on any grid
+fn grid_key(grid: &Grid, key: VirtualKeyCode) -> Route
+{
+    match (key, grid.focus())
+    {
+        (VirtualKeyCode::Return, Some(tile)) =>
+        {
+            Route::Push(Box::new(Screen::Detail(Detail::open(tile.item.clone()))))
+        }
+        _ => Route::Stay,
+    }
+}
+
+fn move_grid(grid: &mut Grid, key: VirtualKeyCode)
+{
+    match key
+    {
+        VirtualKeyCode::Up => grid.up(),
+        VirtualKeyCode::Down => grid.down(),
+        VirtualKeyCode::Left => grid.left(),
+        VirtualKeyCode::Right => grid.right(),
+        _ => (),
+    }
+}
+
+// `set` wrapped into rows of `columns` tiles, titled on the first row only
+fn tiled(set: &Set, columns: usize, navigation: Navigation) -> Grid
+{
+    let mut grid = Grid::new(Prefetcher::new(PrefetchConfig::from_env()), navigation);
+    for (index, items) in set.items.chunks(columns).enumerate()
+    {
+        let mut row = Set::new(
+            if index == 0
+            {
+                set.title.clone()
+            }
+            else
+            {
+                String::new()
+            },
+        );
+        row.items = items.to_vec();
+        grid.add(row);
+    }
+    grid
+}
+
+impl Screen
+{
+    fn key(&mut self, key: VirtualKeyCode, preferences: &mut Preferences, home: &mut Grid)
+        -> Route
+    {
+        match self
+        {
+            Screen::Detail(detail) =>
+            {
+                if matches!(key, VirtualKeyCode::Escape | VirtualKeyCode::Back)
+                {
+                    detail.close();
+                }
+                Route::Stay
+            }
+            Screen::Set(grid) => match key
+            {
+                VirtualKeyCode::Escape | VirtualKeyCode::Back => Route::Back,
+                _ =>
+                {
+                    move_grid(grid, key);
+                    grid_key(grid, key)
+                }
+            },
+            Screen::Search(search) => search.key(key),
+            Screen::Settings(settings) => settings.key(key, preferences),
+            Screen::Quit => match key
+            {
+                VirtualKeyCode::Return => Route::Quit,
+                VirtualKeyCode::Escape | VirtualKeyCode::Back => Route::Back,
+                _ =>
+                {
+                    // the grid underneath keeps working, the question just waits
+                    move_grid(home, key);
+                    Route::Stay
+                }
+            },
+        }
+    }
+
+    fn grid(&self) -> Option<&Grid>
+    {
+        match self
+        {
+            Screen::Set(grid) => Some(grid),
+            Screen::Search(search) => Some(&search.results),
+            _ => None,
+        }
+    }
+
+    fn grid_mut(&mut self) -> Option<&mut Grid>
+    {
+        match self
+        {
+            Screen::Set(grid) => Some(grid),
+            Screen::Search(search) => Some(&mut search.results),
+            _ => None,
+        }
+    }
+
+    fn is_opaque(&self) -> bool { self.grid().is_some() }
+
+    fn focus_matrix(&self, projection: Mat4) -> Option<Mat4>
+    {
+        match self
+        {
+            Screen::Set(grid) => grid.focus_matrix(projection),
+            Screen::Search(search) => search.results.focus_matrix(search.matrix(projection)),
+            _ => None,
+        }
+    }
+
+    fn draw(
+        &self,
+        frame: &mut Frame,
+        projection: Mat4,
+        width: f32,
+        context: Arc<Renderers>,
+        texture_cache: &TextureCache,
+        preferences: &Preferences,
+    )
+    {
+        match self
+        {
+            // drawn by the router, which knows the screen below it grows out of
+            Screen::Detail(_) => (),
+            Screen::Set(grid) => grid.draw(frame, projection, context, texture_cache),
+            Screen::Search(search) => search.draw(frame, projection, width, context, texture_cache),
+            Screen::Settings(settings) =>
+            {
+                settings.draw(frame, projection, width, context, preferences)
+            }
+            Screen::Quit =>
+            {
+                let lines = [
+                    ("Leave lean back?".to_string(), false),
+                    ("Enter to quit, Escape to stay".to_string(), false),
+                ];
+                draw_panel(frame, projection, width, context, &lines);
+            }
+        }
+    }
+}
+
+// filters every title on the home screen as the query is typed
+pub struct Search
+{
+    query:       String,
+    items:       Vec<Item>,
+    pub results: Grid,
+    // the '/' that opened search arrives as a character straight after, it isn't part of the query
+    opened_with: Option<char>,
+}
+
+impl Search
+{
+    // the results sit under the query line
+    const TOP: f32 = 0.6;
+
+    fn new(home: &Grid) -> Self
+    {
+        // the same title can be in several sets
+        let mut seen = HashSet::new();
+        let items = home
+            .rows
+            .iter()
+            .flat_map(|row| row.set.items.iter())
+            .filter(|item| seen.insert(item.image_url.clone()))
+            .cloned()
+            .collect();
+        Self {
+            query: String::new(),
+            items,
+            results: Grid::new(Prefetcher::new(PrefetchConfig::from_env()), home.navigation),
+            opened_with: Some('/'),
+        }
+    }
+
+    fn key(&mut self, key: VirtualKeyCode) -> Route
+    {
+        match key
+        {
+            VirtualKeyCode::Escape => Route::Back,
+            // Backspace edits the query, and only goes back once there is nothing left to delete
+            VirtualKeyCode::Back => match self.query.pop()
+            {
+                Some(_) =>
+                {
+                    self.refresh(self.results.viewport.columns.floor().max(1.0) as usize);
+                    Route::Stay
+                }
+                None => Route::Back,
+            },
+            _ =>
+            {
+                move_grid(&mut self.results, key);
+                grid_key(&self.results, key)
+            }
+        }
+    }
+
+    fn type_character(&mut self, character: char, columns: usize, navigation: Navigation)
+    {
+        // Backspace, Enter and Escape come through as characters too
+        if character.is_control() || self.opened_with.take() == Some(character)
+        {
+            return;
+        }
+        self.query.push(character);
+        self.results.navigation = navigation;
+        self.refresh(columns);
+    }
+
+    fn refresh(&mut self, columns: usize)
+    {
+        let query = self.query.to_lowercase();
+        let mut set = Set::new(format!("{} results", self.query));
+        if !query.trim().is_empty()
+        {
+            set.items = self
+                .items
+                .iter()
+                .filter(|item| {
+                    item.title
+                        .as_ref()
+                        .is_some_and(|title| title.to_lowercase().contains(&query))
+                })
+                .cloned()
+                .collect();
+        }
+        let viewport = self.results.viewport;
+        self.results = tiled(&set, columns, self.results.navigation);
+        self.results.viewport = viewport;
+    }
+
+    fn matrix(&self, projection: Mat4) -> Mat4
+    {
+        projection * Mat4::from_translation(Vec3::new(0.0, Self::TOP, 0.0))
+    }
+
+    fn draw(
+        &self,
+        frame: &mut Frame,
+        projection: Mat4,
+        width: f32,
+        context: Arc<Renderers>,
+        texture_cache: &TextureCache,
+    )
+    {
+        let query = format!("Search: {}_", self.query);
+        let line = Mat4::from_translation(Vec3::new(0.15, 0.15, 0.0));
+        context.text_renderer.draw(
+            frame,
+            projection * line,
+            &query,
+            0.3,
+            width - 0.3,
+            Vec4::ONE,
+        );
+        self.results
+            .draw(frame, self.matrix(projection), context, texture_cache);
+    }
+}
+
+// a short list of switches, Up and Down pick one and Enter flips it
+#[derive(Default)]
+pub struct Settings
+{
+    selection: usize,
+}
+
+impl Settings
+{
+    const COUNT: usize = 2;
+
+    fn key(&mut self, key: VirtualKeyCode, preferences: &mut Preferences) -> Route
+    {
+        match key
+        {
+            VirtualKeyCode::Escape | VirtualKeyCode::Back => return Route::Back,
+            VirtualKeyCode::Up => self.selection = self.selection.saturating_sub(1),
+            VirtualKeyCode::Down => self.selection = (self.selection + 1).min(Self::COUNT - 1),
+            VirtualKeyCode::Return => match self.selection
+            {
+                0 => preferences.show_stats = !preferences.show_stats,
+                _ =>
+                {
+                    preferences.navigation = match preferences.navigation
+                    {
+                        Navigation::Pinned => Navigation::Viewport(preferences.safe_area),
+                        Navigation::Viewport(_) => Navigation::Pinned,
+                    }
+                }
+            },
+            _ => (),
+        }
+        Route::Stay
+    }
+
+    fn draw(
+        &self,
+        frame: &mut Frame,
+        projection: Mat4,
+        width: f32,
+        context: Arc<Renderers>,
+        preferences: &Preferences,
+    )
+    {
+        let stats = if preferences.show_stats { "on" } else { "off" };
+        let navigation = match preferences.navigation
+        {
+            Navigation::Pinned => "pinned",
+            Navigation::Viewport(_) => "viewport",
+        };
+        let lines = [
+            (format!("Stats overlay: {}", stats), self.selection == 0),
+            (format!("Navigation: {}", navigation), self.selection == 1),
+        ];
+        draw_panel(frame, projection, width, context, &lines);
+    }
+}
+
+// a dark box in the middle of the screen with a line of text per entry, the ones marked true
+// are highlighted.  Drawn in front of everything, detail view included
+fn draw_panel(
+    frame: &mut Frame,
+    projection: Mat4,
+    width: f32,
+    context: Arc<Renderers>,
+    lines: &[(String, bool)],
+)
+{
+    const Z: f32 = 9.0;
+    const LINE: f32 = 0.4;
+    const SIZE: f32 = 0.2;
+    const PADDING: f32 = 0.3;
+    let panel_width = 4.0_f32.min(width);
+    let height = lines.len() as f32 * LINE + 2.0 * PADDING;
+    let (x, y) = ((width - panel_width) / 2.0, (5.0 - height) / 2.0);
+
+    let panel = Mat4::from_translation(Vec3::new(x, y, Z))
+        * Mat4::from_scale(Vec3::new(panel_width, height, 1.0));
+    context
+        .color_tile_renderer
+        .draw(frame, projection * panel, Vec4::new(0.08, 0.08, 0.1, 1.0));
+
+    for (index, (text, highlighted)) in lines.iter().enumerate()
+    {
+        let top = y + PADDING + index as f32 * LINE;
+        if *highlighted
+        {
+            let bar = Mat4::from_translation(Vec3::new(x, top, Z + 0.01))
+                * Mat4::from_scale(Vec3::new(panel_width, LINE, 1.0));
+            context.color_tile_renderer.draw(
+                frame,
+                projection * bar,
+                Vec4::new(0.25, 0.25, 0.3, 1.0),
+            );
+        }
+        let line =
+            Mat4::from_translation(Vec3::new(x + PADDING, top + (LINE - SIZE) / 2.0, Z + 0.02));
+        context.text_renderer.draw(
+            frame,
+            projection * line,
+            text,
+            SIZE,
+            panel_width - 2.0 * PADDING,
+            Vec4::ONE,
+        );
+    }
+}
+
+// the selected tile blown up to fill the screen, with its art dimmed behind the title,
+// details and description.  It grows out of the tile's spot in the grid and shrinks back
+// into it, wherever that is on the frame it is drawn
+pub struct Detail
+{
+    pub item: Item,
+    // 0.0 is the tile sitting in the grid, 1.0 is the full screen
+    openness: Animation<f32>,
+}
+
+impl Detail
+{
+    const DIM: f32 = 0.6;
+    const DURATION: Duration = Duration::from_millis(350);
+    const LINES: usize = 5;
+    const MARGIN: f32 = 0.4;
+    // the detail view is drawn this far towards the viewer, in front of everything in the grid
+    // (the selected tile is lifted to 5)
+    const Z: f32 = 8.0;
+
+    pub fn open(item: Item) -> Self
+    {
+        let mut openness = Animation::new(0.0, Self::DURATION, Easing::CubicInOut);
+        openness.next(1.0);
+        Self { item, openness }
+    }
+
+    // heads back to the grid from wherever the opening got to
+    pub fn close(&mut self)
+    {
+        if self.openness.end > 0.0
+        {
+            self.openness.next(0.0);
+        }
+    }
+
+    pub fn is_closed(&self) -> bool { self.openness.end == 0.0 && self.openness.is_done() }
+
+    fn openness(&self) -> f32 { clamp(self.openness.value()) }
+
+    // `tile` is where the tile is drawn in the grid right now and `width` how many units
+    // across the screen is, the projection is always 5 tall
+    pub fn draw(
+        &self,
+        frame: &mut Frame,
+        projection: Mat4,
+        tile: Mat4,
+        width: f32,
+        context: Arc<Renderers>,
+        texture_cache: &TextureCache,
+    )
+    {
+        let openness = self.openness();
+        let screen = projection
+            * Mat4::from_translation(Vec3::new(0.0, 0.0, Self::Z))
+            * Mat4::from_scale(Vec3::new(width, 5.0, 1.0));
+        // nudged off the tile so it starts out in front of its caption
+        let tile = tile * Mat4::from_translation(Vec3::new(0.0, 0.0, 0.05));
+        let matrix = lerp(&tile, &screen, openness);
+
+        // a black backdrop under the art keeps the grid from showing through transparent art
+        let behind = Mat4::from_translation(Vec3::new(0.0, 0.0, -0.01));
+        context
+            .color_tile_renderer
+            .draw(frame, matrix * behind, Vec4::new(0.0, 0.0, 0.0, 1.0));
+
+        let url = &self.item.image_url;
+        let brightness = 1.0 - Self::DIM * openness;
+        match (texture_cache.lookup(url), texture_cache.previews.get(url))
+        {
+            (Some(art), _) =>
+            {
+                context
+                    .texture_tile_renderer
+                    .draw(frame, matrix, &art.texture, brightness, 1.0)
+            }
+            (None, Some(preview)) =>
+            {
+                context
+                    .texture_tile_renderer
+                    .draw(frame, matrix, &preview.texture, brightness, 1.0)
+            }
+            (None, None) =>
+            {
+                let color = texture_cache
+                    .palettes
+                    .get(url)
+                    .map(|palette| palette.average())
+                    .unwrap_or(Vec3::ONE);
+                context
+                    .color_tile_renderer
+                    .draw(frame, matrix, (color * brightness).extend(1.0));
+            }
+        }
+
+        // the text waits until the art has nearly filled the screen
+        let opacity = clamp((openness - 0.6) / 0.4);
+        if opacity > 0.0
+        {
+            self.draw_text(frame, projection, width, context, opacity);
+        }
+    }
+
+    fn draw_text(
+        &self,
+        frame: &mut Frame,
+        projection: Mat4,
+        width: f32,
+        context: Arc<Renderers>,
+        opacity: f32,
+    )
+    {
+        let text_width = width / 2.0 - Self::MARGIN;
+        let mut lines = vec![];
+        if let Some(title) = &self.item.title
+        {
+            lines.push((title.clone(), 0.4, Vec3::ONE, 0.1));
+        }
+        let details = self.item.details();
+        if !details.is_empty()
+        {
+            lines.push((details, 0.18, Vec3::splat(0.8), 0.2));
+        }
+        if let Some(description) = &self.item.description
+        {
+            let size = 0.16;
+            let wrapped =
+                context
+                    .text_renderer
+                    .atlas
+                    .wrap(description, text_width / size, Self::LINES);
+            for line in wrapped
+            {
+                lines.push((line, size, Vec3::splat(0.9), 0.05));
+            }
+        }
+
+        let mut y = 2.4;
+        for (text, size, color, space) in lines
+        {
+            let line = Mat4::from_translation(Vec3::new(Self::MARGIN, y, Self::Z + 0.01));
+            context.text_renderer.draw(
+                frame,
+                projection * line,
+                &text,
+                size,
+                text_width,
+                color.extend(opacity),
+            );
+            y += size + space;
+        }
+    }
+}
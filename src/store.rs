@@ -0,0 +1,171 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        OnceLock,
+    },
+    time::SystemTime,
+};
+
+use anyhow::Error;
+use bytes::Bytes;
+
+static ROOT: OnceLock<PathBuf> = OnceLock::new();
+
+// when offline the disk cache is the only source of feeds and remote images, a miss is an error
+static OFFLINE: AtomicBool = AtomicBool::new(false);
+
+pub fn go_offline() { OFFLINE.store(true, Ordering::Relaxed); }
+
+pub fn is_offline() -> bool { OFFLINE.load(Ordering::Relaxed) }
+
+// where the disk cache lives.  It is settled the first time anyone asks, after that set_root
+// can no longer move it
+pub fn root() -> &'static Path { ROOT.get_or_init(default_root) }
+
+// points the disk cache at `dir` instead of the default, before anything has used it
+pub fn set_root(dir: PathBuf) -> Result<(), Error>
+{
+    ROOT.set(dir)
+        .map_err(|_| anyhow!("the disk cache is already open in {}", root().display()))
+}
+
+// the disk cache lives in LEAN_BACK_CACHE_DIR if it is set, otherwise in ~/.cache/lean-back.
+// Every image is stored under images/ in a file named after a hash of its url, next to
+// its blurred preview (the same name with a .preview extension) and its colors (.palette).  The json feeds that
+// describe the collection are kept under feeds/ the same way
+fn default_root() -> PathBuf
+{
+    if let Ok(dir) = std::env::var("LEAN_BACK_CACHE_DIR")
+    {
+        return PathBuf::from(dir);
+    }
+    match std::env::var("HOME")
+    {
+        Ok(home) => PathBuf::from(home).join(".cache").join("lean-back"),
+        Err(_) => std::env::temp_dir().join("lean-back"),
+    }
+}
+
+pub fn images_dir() -> PathBuf { root().join("images") }
+
+pub fn image_path(url: &str) -> PathBuf { images_dir().join(format!("{:016x}", hash(url))) }
+
+pub fn preview_path(url: &str) -> PathBuf { image_path(url).with_extension("preview") }
+
+pub fn palette_path(url: &str) -> PathBuf { image_path(url).with_extension("palette") }
+
+pub fn feeds_dir() -> PathBuf { root().join("feeds") }
+
+pub fn feed_path(url: &str) -> PathBuf { feeds_dir().join(format!("{:016x}.json", hash(url))) }
+
+pub async fn read_image(url: &str) -> Option<Bytes> { read(image_path(url)).await }
+
+pub async fn write_image(url: &str, bytes: &[u8]) -> Result<(), Error>
+{
+    write(image_path(url), bytes).await
+}
+
+pub async fn read_preview(url: &str) -> Option<Bytes> { read(preview_path(url)).await }
+
+pub async fn has_preview(url: &str) -> bool { tokio::fs::metadata(preview_path(url)).await.is_ok() }
+
+pub async fn write_preview(url: &str, bytes: &[u8]) -> Result<(), Error>
+{
+    write(preview_path(url), bytes).await
+}
+
+pub async fn read_palette(url: &str) -> Option<Bytes> { read(palette_path(url)).await }
+
+pub async fn has_palette(url: &str) -> bool { tokio::fs::metadata(palette_path(url)).await.is_ok() }
+
+pub async fn write_palette(url: &str, bytes: &[u8]) -> Result<(), Error>
+{
+    write(palette_path(url), bytes).await
+}
+
+pub async fn read_feed(url: &str) -> Option<Bytes> { read(feed_path(url)).await }
+
+pub async fn write_feed(url: &str, bytes: &[u8]) -> Result<(), Error>
+{
+    write(feed_path(url), bytes).await
+}
+
+async fn read(path: PathBuf) -> Option<Bytes>
+{
+    match tokio::fs::read(path).await
+    {
+        Ok(bytes) if !bytes.is_empty() => Some(Bytes::from(bytes)),
+        _ => None,
+    }
+}
+
+// written to a temporary file first and then renamed, so a crash mid write never leaves
+// a truncated image behind under the real name
+async fn write(path: PathBuf, bytes: &[u8]) -> Result<(), Error>
+{
+    if let Some(dir) = path.parent()
+    {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+    let mut partial = path.clone().into_os_string();
+    partial.push(".partial");
+    tokio::fs::write(&partial, bytes).await?;
+    tokio::fs::rename(&partial, &path).await?;
+    Ok(())
+}
+
+pub async fn remove_image(url: &str) -> Result<(), Error>
+{
+    tokio::fs::remove_file(image_path(url)).await?;
+    Ok(())
+}
+
+pub struct CachedFile
+{
+    pub path:     PathBuf,
+    pub size:     u64,
+    pub modified: SystemTime,
+}
+
+// everything in the disk cache, images, previews and feeds alike
+pub fn list() -> Vec<CachedFile>
+{
+    let mut files = vec![];
+    for dir in [images_dir(), feeds_dir()]
+    {
+        let entries = match std::fs::read_dir(dir)
+        {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries.flatten()
+        {
+            if let Ok(metadata) = entry.metadata()
+            {
+                if metadata.is_file()
+                {
+                    files.push(CachedFile {
+                        path:     entry.path(),
+                        size:     metadata.len(),
+                        modified: metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+                    });
+                }
+            }
+        }
+    }
+    files
+}
+
+// 64 bit FNV-1a.  std's DefaultHasher is not guaranteed to be stable between rust releases,
+// and the file names have to survive an upgrade
+fn hash(value: &str) -> u64
+{
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in value.as_bytes()
+    {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
@@ -0,0 +1,380 @@
+use std::{borrow::Cow, collections::HashMap};
+
+use glam::{Mat4, Vec3, Vec4};
+use glium::{
+    texture::{ClientFormat, MipmapsOption, RawImage2d, Texture2d, UncompressedFloatFormat},
+    uniforms::{MagnifySamplerFilter, MinifySamplerFilter},
+    Display, Frame, Surface,
+};
+use rusttype::{point, Font, Scale};
+
+use crate::ux::Vertex;
+
+const FONT: &[u8] = include_bytes!("../assets/fonts/DejaVuSans.ttf");
+
+const ELLIPSIS: char = '\u{2026}';
+
+// where a glyph lives in the atlas and how to place it.  Everything but `uv` is in ems, so
+// text can be drawn at any size from the one rasterization
+#[derive(Copy, Clone, Debug)]
+struct Glyph
+{
+    // x, y, width, height in texture coordinates, y going down from the top of the atlas
+    uv:      [f32; 4],
+    // the top left corner of the glyph's bitmap relative to the pen on the baseline
+    offset:  [f32; 2],
+    size:    [f32; 2],
+    advance: f32,
+}
+
+// one glyph of laid out text, `x` is the pen position along the baseline in ems
+#[derive(Copy, Clone, Debug)]
+pub struct Placed
+{
+    pub character: char,
+    pub x:         f32,
+}
+
+// every glyph the titles can use, rasterized once at startup into a single channel texture
+pub struct GlyphAtlas
+{
+    font:       Font<'static>,
+    texture:    Texture2d,
+    glyphs:     HashMap<char, Glyph>,
+    // distance from the top of a line to the baseline, in ems
+    pub ascent: f32,
+}
+
+impl GlyphAtlas
+{
+    // empty pixels around every glyph so linear filtering doesn't bleed the neighbours in
+    const PADDING: u32 = 2;
+    // glyphs are rasterized this many pixels tall, comfortably more than titles are drawn at
+    const PIXELS: f32 = 64.0;
+    const SIZE: u32 = 1024;
+
+    pub fn new(display: &Display) -> Self
+    {
+        let font = Font::try_from_bytes(FONT).expect("the bundled font is broken");
+        let scale = Scale::uniform(Self::PIXELS);
+        let ascent = font.v_metrics(scale).ascent / Self::PIXELS;
+
+        // printable ascii and latin-1 cover the feed's titles, plus the ellipsis for truncation
+        let characters = (' '..='~').chain('\u{a1}'..='\u{ff}').chain([ELLIPSIS]);
+
+        // a simple shelf packer: glyphs go left to right and start a new shelf when the row is full
+        let mut pixels = vec![0u8; (Self::SIZE * Self::SIZE) as usize];
+        let mut glyphs = HashMap::new();
+        let (mut x, mut y, mut shelf) = (Self::PADDING, Self::PADDING, 0);
+        for character in characters
+        {
+            let glyph = font.glyph(character);
+            // the font's .notdef box, better to fall back to '?' when laying out
+            if glyph.id().0 == 0
+            {
+                continue;
+            }
+            let glyph = glyph.scaled(scale);
+            let advance = glyph.h_metrics().advance_width / Self::PIXELS;
+            let glyph = glyph.positioned(point(0.0, 0.0));
+            let bounds = match glyph.pixel_bounding_box()
+            {
+                Some(bounds) => bounds,
+                // spaces have an advance but nothing to draw
+                None =>
+                {
+                    glyphs.insert(
+                        character,
+                        Glyph {
+                            uv: [0.0; 4],
+                            offset: [0.0; 2],
+                            size: [0.0; 2],
+                            advance,
+                        },
+                    );
+                    continue;
+                }
+            };
+            let (width, height) = (bounds.width() as u32, bounds.height() as u32);
+            if x + width + Self::PADDING > Self::SIZE
+            {
+                x = Self::PADDING;
+                y += shelf + Self::PADDING;
+                shelf = 0;
+            }
+            if y + height + Self::PADDING > Self::SIZE
+            {
+                eprintln!("the glyph atlas is full, {:?} is left out", character);
+                continue;
+            }
+            glyph.draw(|gx, gy, coverage| {
+                let index = (y + gy) * Self::SIZE + x + gx;
+                pixels[index as usize] = (coverage * 255.0) as u8;
+            });
+            let atlas = Self::SIZE as f32;
+            glyphs.insert(
+                character,
+                Glyph {
+                    uv: [
+                        x as f32 / atlas,
+                        y as f32 / atlas,
+                        width as f32 / atlas,
+                        height as f32 / atlas,
+                    ],
+                    offset: [
+                        bounds.min.x as f32 / Self::PIXELS,
+                        bounds.min.y as f32 / Self::PIXELS,
+                    ],
+                    size: [width as f32 / Self::PIXELS, height as f32 / Self::PIXELS],
+                    advance,
+                },
+            );
+            x += width + Self::PADDING;
+            shelf = shelf.max(height);
+        }
+
+        // rows go up top first, so v = 0 is the top of the atlas
+        let image = RawImage2d {
+            data:   Cow::Owned(pixels),
+            width:  Self::SIZE,
+            height: Self::SIZE,
+            format: ClientFormat::U8,
+        };
+        let texture = Texture2d::with_format(
+            display,
+            image,
+            UncompressedFloatFormat::U8,
+            MipmapsOption::NoMipmap,
+        )
+        .unwrap();
+
+        Self {
+            font,
+            texture,
+            glyphs,
+            ascent,
+        }
+    }
+
+    // places `text` along a baseline with kerning, in ems.  If it is wider than `max_width`
+    // it is cut short at a character boundary and finished with an ellipsis
+    pub fn layout(&self, text: &str, max_width: f32) -> (Vec<Placed>, f32)
+    {
+        let (mut placed, width) = self.place(text.chars());
+        if width <= max_width
+        {
+            return (placed, width);
+        }
+
+        let ellipsis = self.glyph(ELLIPSIS).advance;
+        // the ellipsis takes the place of the last character dropped, and never follows a space
+        while let Some(dropped) = placed.pop()
+        {
+            let after_space = placed
+                .last()
+                .is_some_and(|placed| placed.character.is_whitespace());
+            if dropped.x + ellipsis <= max_width && !after_space
+            {
+                break;
+            }
+        }
+        // re-placed rather than appended so the ellipsis is kerned against the last character
+        let characters: Vec<char> = placed.iter().map(|placed| placed.character).collect();
+        self.place(characters.into_iter().chain([ELLIPSIS]))
+    }
+
+    // breaks `text` into at most `max_lines` lines no wider than `max_width` ems, between words.
+    // Whatever doesn't fit goes on the last line, which layout then cuts short with an ellipsis
+    pub fn wrap(&self, text: &str, max_width: f32, max_lines: usize) -> Vec<String>
+    {
+        let mut lines = vec![];
+        let mut words = text.split_whitespace().peekable();
+        while words.peek().is_some() && lines.len() < max_lines
+        {
+            let mut line = String::new();
+            if lines.len() + 1 == max_lines
+            {
+                line = words.by_ref().collect::<Vec<_>>().join(" ");
+            }
+            while let Some(word) = words.peek()
+            {
+                let candidate = if line.is_empty()
+                {
+                    word.to_string()
+                }
+                else
+                {
+                    format!("{} {}", line, word)
+                };
+                // a word too long for a line of its own still gets one
+                if !line.is_empty() && self.place(candidate.chars()).1 > max_width
+                {
+                    break;
+                }
+                line = candidate;
+                words.next();
+            }
+            lines.push(line);
+        }
+        lines
+    }
+
+    fn place(&self, characters: impl Iterator<Item = char>) -> (Vec<Placed>, f32)
+    {
+        let scale = Scale::uniform(Self::PIXELS);
+        let mut placed = vec![];
+        let mut pen = 0.0;
+        let mut previous = None;
+        for character in characters.map(|character| self.fallback(character))
+        {
+            if let Some(previous) = previous
+            {
+                pen += self.font.pair_kerning(scale, previous, character) / Self::PIXELS;
+            }
+            placed.push(Placed { character, x: pen });
+            pen += self.glyph(character).advance;
+            previous = Some(character);
+        }
+        (placed, pen)
+    }
+
+    fn fallback(&self, character: char) -> char
+    {
+        if self.glyphs.contains_key(&character)
+        {
+            character
+        }
+        else if character.is_whitespace()
+        {
+            ' '
+        }
+        else
+        {
+            '?'
+        }
+    }
+
+    fn glyph(&self, character: char) -> Glyph { self.glyphs[&self.fallback(character)] }
+}
+
+// draws single lines of text one glyph at a time, the same way TileRenderer draws tiles:
+// a unit quad stretched over each glyph, sampling its corner of the atlas
+pub struct TextRenderer
+{
+    pub atlas:     GlyphAtlas,
+    vertex_buffer: glium::VertexBuffer<Vertex>,
+    program:       glium::Program,
+    indices:       glium::index::NoIndices,
+}
+
+impl TextRenderer
+{
+    pub fn new(display: &Display) -> Self
+    {
+        let shape = vec![
+            Vertex::new(0.0, 0.0),
+            Vertex::new(0.0, 1.0),
+            Vertex::new(1.0, 0.0),
+            Vertex::new(1.0, 1.0),
+            Vertex::new(0.0, 1.0),
+            Vertex::new(1.0, 0.0),
+        ];
+        let vertex_buffer = glium::VertexBuffer::new(display, &shape).unwrap();
+        let indices = glium::index::NoIndices(glium::index::PrimitiveType::TrianglesList);
+
+        let vertex_shader_src = r#"
+        #version 140
+        in vec2 position;
+        out vec2 v_tex_coords;
+        uniform mat4 matrix;
+        uniform vec4 uv_rect;
+        void main() {
+            v_tex_coords = uv_rect.xy + position * uv_rect.zw;
+            gl_Position = matrix * vec4(position, 0.0, 1.0);
+        }
+    "#;
+
+        let fragment_shader_src = r#"
+        #version 140
+        in vec2 v_tex_coords;
+        out vec4 color;
+        uniform sampler2D atlas;
+        uniform vec4 color_in;
+        void main() {
+            float coverage = texture(atlas, v_tex_coords).r;
+            color = vec4(color_in.rgb, color_in.a * coverage);
+        }
+    "#;
+
+        let program =
+            glium::Program::from_source(display, vertex_shader_src, fragment_shader_src, None)
+                .unwrap();
+
+        Self {
+            atlas: GlyphAtlas::new(display),
+            vertex_buffer,
+            program,
+            indices,
+        }
+    }
+
+    // draws `text` with the top of the line at the origin of `matrix`, `size` units tall.
+    // Anything that would run past `max_width` units is replaced with an ellipsis
+    pub fn draw(
+        &self,
+        frame: &mut Frame,
+        matrix: Mat4,
+        text: &str,
+        size: f32,
+        max_width: f32,
+        color: Vec4,
+    )
+    {
+        let (placed, _) = self.atlas.layout(text, max_width / size);
+        let baseline = self.atlas.ascent;
+        let params = glium::DrawParameters {
+            // tested so lifted tiles cover the titles, but not written since glyph quads overlap
+            depth: glium::Depth {
+                test: glium::draw_parameters::DepthTest::IfLess,
+                write: false,
+                ..Default::default()
+            },
+            blend: glium::Blend::alpha_blending(),
+            ..Default::default()
+        };
+        for placed in placed
+        {
+            let glyph = self.atlas.glyph(placed.character);
+            if glyph.size[0] == 0.0
+            {
+                continue;
+            }
+            let matrix = matrix
+                * Mat4::from_scale(Vec3::new(size, size, 1.0))
+                * Mat4::from_translation(Vec3::new(
+                    placed.x + glyph.offset[0],
+                    baseline + glyph.offset[1],
+                    0.0,
+                ))
+                * Mat4::from_scale(Vec3::new(glyph.size[0], glyph.size[1], 1.0));
+            let uniforms = uniform! {
+                matrix: matrix.to_cols_array_2d(),
+                uv_rect: glyph.uv,
+                atlas: self.atlas.texture
+                    .sampled()
+                    .minify_filter(MinifySamplerFilter::Linear)
+                    .magnify_filter(MagnifySamplerFilter::Linear),
+                color_in: color.to_array(),
+            };
+            frame
+                .draw(
+                    &self.vertex_buffer,
+                    self.indices,
+                    &self.program,
+                    &uniforms,
+                    &params,
+                )
+                .unwrap();
+        }
+    }
+}
@@ -0,0 +1,196 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use anyhow::Error;
+use glium::{
+    texture::{MipmapsOption, SrgbTexture2d},
+    Display,
+};
+
+use crate::{
+    cache::Pixels,
+    metrics::{Metrics, METRICS},
+    palette::Palette,
+    ux::Grid,
+};
+
+// limits how much texture data is pushed to the GPU in a single frame.  Uploading a burst of
+// freshly decoded images all at once stalls the frame and makes the Lerper animations hitch
+#[derive(Clone, Debug)]
+pub struct UploadBudget
+{
+    pub bytes: usize,
+    pub count: usize,
+}
+
+impl Default for UploadBudget
+{
+    fn default() -> Self
+    {
+        Self {
+            bytes: 8 * 1024 * 1024,
+            count: 4,
+        }
+    }
+}
+
+// an uploaded texture and the moment it was uploaded, so tiles can fade it in over whatever
+// they were showing before
+pub struct Art
+{
+    pub texture: SrgbTexture2d,
+    pub arrived: Instant,
+}
+
+impl Art
+{
+    // how far faded in the texture is, fading in over `fade` from when it arrived
+    pub fn opacity(&self, fade: Duration) -> f32
+    {
+        (self.arrived.elapsed().as_secs_f32() / fade.as_secs_f32()).clamp(0.0, 1.0)
+    }
+}
+
+// every texture the UX can draw, keyed by image url
+#[derive(Default)]
+pub struct TextureCache
+{
+    pub art:      HashMap<String, Art>,
+    // tiny blurred stand-ins shown until the art arrives
+    pub previews: HashMap<String, Art>,
+    // the art's colors, there is nothing to upload so they skip the queue
+    pub palettes: HashMap<String, Palette>,
+}
+
+impl TextureCache
+{
+    // the art for `url` if it has been uploaded, counted as a memory hit or miss.  Only drawing
+    // goes through here, so the hit rate is the share of tiles drawn with their art
+    pub fn lookup(&self, url: &str) -> Option<&Art>
+    {
+        let art = self.art.get(url);
+        let counter = match art
+        {
+            Some(_) => &METRICS.memory_hits,
+            None => &METRICS.memory_misses,
+        };
+        Metrics::increment(counter);
+        art
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum UploadKind
+{
+    Art,
+    Preview,
+}
+
+// images that have been decoded by the cacher and are waiting for their turn to become textures
+pub struct UploadQueue
+{
+    pub budget: UploadBudget,
+    pending:    Vec<(String, UploadKind, Pixels)>,
+}
+
+impl UploadQueue
+{
+    pub fn new(budget: UploadBudget) -> Self
+    {
+        Self {
+            budget,
+            pending: vec![],
+        }
+    }
+
+    pub fn push(&mut self, url: String, kind: UploadKind, pixels: Pixels)
+    {
+        self.pending.push((url, kind, pixels));
+    }
+
+    // uploads the most important pending images (as ranked by the grid) until the budget is spent.
+    // at least one image is always uploaded so a single oversized image can never stall the queue.
+    // previews are tiny and go first so every tile gets something to show as soon as possible
+    pub fn drain(&mut self, display: &Display, grid: &Grid, texture_cache: &mut TextureCache)
+    {
+        if self.pending.is_empty()
+        {
+            return;
+        }
+
+        let priorities = grid.priorities();
+        self.pending.sort_by_key(|(url, kind, _)| {
+            (
+                *kind != UploadKind::Preview,
+                priorities.get(url).cloned().unwrap_or(Priority::UNKNOWN),
+            )
+        });
+
+        let mut bytes = 0;
+        let mut count = 0;
+        while !self.pending.is_empty()
+        {
+            let size = self.pending[0].2.data.len();
+            if count > 0 && (count >= self.budget.count || bytes + size > self.budget.bytes)
+            {
+                break;
+            }
+            let (url, kind, pixels) = self.pending.remove(0);
+            bytes += size;
+            count += 1;
+            match (upload(pixels, display), kind)
+            {
+                (Ok(texture), UploadKind::Art) =>
+                {
+                    println!("Cached: {}", url);
+                    let art = Art {
+                        texture,
+                        arrived: Instant::now(),
+                    };
+                    texture_cache.art.insert(url, art);
+                }
+                (Ok(texture), UploadKind::Preview) =>
+                {
+                    let preview = Art {
+                        texture,
+                        arrived: Instant::now(),
+                    };
+                    texture_cache.previews.insert(url, preview);
+                }
+                (Err(error), _) =>
+                {
+                    println!("ToTexture: {} ERROR: {}", url, error);
+                }
+            }
+        }
+    }
+}
+
+// lower sorts first.  Visible tiles always come before tiles that are scrolled out of view
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub struct Priority
+{
+    pub hidden:   bool,
+    pub distance: usize,
+}
+
+impl Priority
+{
+    pub const UNKNOWN: Priority = Priority {
+        hidden:   true,
+        distance: usize::MAX,
+    };
+}
+
+// mipmaps are generated on the GPU so tiles drawn smaller than their art don't shimmer while scrolling
+fn upload(pixels: Pixels, display: &Display) -> Result<SrgbTexture2d, Error>
+{
+    let image = glium::texture::RawImage2d::from_raw_rgba(pixels.data, pixels.dimensions);
+    Ok(SrgbTexture2d::with_mipmaps(
+        display,
+        image,
+        MipmapsOption::AutoGeneratedMipmaps,
+    )?)
+}
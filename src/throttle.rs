@@ -0,0 +1,111 @@
+use std::{
+    sync::{atomic::Ordering, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::metrics::METRICS;
+
+// caps the cacher's download rate with a token bucket holding up to one second of bytes.
+// Downloads spend their bytes chunk by chunk as they come in, which may push the bucket into
+// debt, and every download holds off reading more until the debt is paid off.  So the cap
+// holds while many downloads run at once, not just on average
+pub struct Throttle
+{
+    // bytes per second
+    rate:  f64,
+    state: Mutex<Bucket>,
+}
+
+struct Bucket
+{
+    tokens:   f64,
+    refilled: Instant,
+}
+
+impl Throttle
+{
+    pub fn new(bytes_per_second: usize) -> Self
+    {
+        Self {
+            rate:  bytes_per_second as f64,
+            state: Mutex::new(Bucket {
+                tokens:   bytes_per_second as f64,
+                refilled: Instant::now(),
+            }),
+        }
+    }
+
+    // returns once the bucket is out of debt.  While anyone is waiting METRICS.throttled is
+    // above zero, which is what the UX shows as the bandwidth cap indicator
+    pub async fn wait(&self)
+    {
+        let mut waiting = false;
+        loop
+        {
+            let debt = {
+                let mut bucket = self.state.lock().unwrap();
+                self.refill(&mut bucket);
+                -bucket.tokens
+            };
+            if debt <= 0.0
+            {
+                break;
+            }
+            if !waiting
+            {
+                waiting = true;
+                METRICS.throttled.fetch_add(1, Ordering::Relaxed);
+            }
+            tokio::time::sleep(Duration::from_secs_f64(debt / self.rate)).await;
+        }
+        if waiting
+        {
+            METRICS.throttled.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    // spends the bytes of a chunk that just arrived and returns once the bucket is out of debt
+    pub async fn take(&self, bytes: usize)
+    {
+        self.spend(bytes);
+        self.wait().await;
+    }
+
+    pub fn spend(&self, bytes: usize)
+    {
+        let mut bucket = self.state.lock().unwrap();
+        self.refill(&mut bucket);
+        bucket.tokens -= bytes as f64;
+    }
+
+    fn refill(&self, bucket: &mut Bucket)
+    {
+        let elapsed = bucket.refilled.elapsed().as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.rate);
+        bucket.refilled = Instant::now();
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use super::*;
+
+    #[tokio::test]
+    async fn chunks_are_paced()
+    {
+        let throttle = Throttle::new(10_000);
+        let start = Instant::now();
+        // the bucket starts out full
+        throttle.take(10_000).await;
+        assert!(start.elapsed() < Duration::from_millis(50));
+        // then every chunk waits for its own bytes
+        for _ in 0..4
+        {
+            throttle.take(500).await;
+        }
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(180), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_secs(1), "{:?}", elapsed);
+    }
+}
@@ -0,0 +1,211 @@
+use std::ops::Sub;
+
+use glam::{Mat4, Quat, Vec3};
+
+// a Mat4 pulled apart into the pieces that can be blended on their own.  Blending the raw
+// components of two matrices only works for translation and scale, a rotation in between
+// squashes the tile.  Shear doesn't survive the trip, nothing in the ux uses it
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Transform
+{
+    pub translation: Vec3,
+    // may be negative, for flips
+    pub scale:       Vec3,
+    pub rotation:    Quat,
+}
+
+// how fast a Transform is changing, per second.  `rotation` is the axis scaled by the
+// radians turned
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Motion
+{
+    pub translation: Vec3,
+    pub scale:       Vec3,
+    pub rotation:    Vec3,
+}
+
+impl Transform
+{
+    pub fn from_matrix(matrix: Mat4) -> Self
+    {
+        let (scale, rotation, translation) = matrix.to_scale_rotation_translation();
+        Self {
+            translation,
+            scale,
+            rotation: rotation.normalize(),
+        }
+    }
+
+    pub fn matrix(&self) -> Mat4
+    {
+        Mat4::from_scale_rotation_translation(self.scale, self.rotation, self.translation)
+    }
+
+    // `amount` isn't clamped, past 1.0 keeps going the same way so eased curves can overshoot
+    pub fn interpolate(&self, other: &Self, amount: f32) -> Self
+    {
+        // q and -q are the same rotation, the one closer to ours is the short way round
+        let rotation = if self.rotation.dot(other.rotation) < 0.0
+        {
+            -other.rotation
+        }
+        else
+        {
+            other.rotation
+        };
+        Self {
+            translation: self.translation.lerp(other.translation, amount),
+            scale:       self.scale.lerp(other.scale, amount),
+            rotation:    self.rotation.slerp(rotation, amount).normalize(),
+        }
+    }
+
+    // where this ends up after moving at `motion` for `seconds`
+    pub fn moved(&self, motion: &Motion, seconds: f32) -> Self
+    {
+        Self {
+            translation: self.translation + motion.translation * seconds,
+            scale:       self.scale + motion.scale * seconds,
+            rotation:    (Quat::from_scaled_axis(motion.rotation * seconds) * self.rotation)
+                .normalize(),
+        }
+    }
+}
+
+impl Motion
+{
+    pub const ZERO: Self = Self {
+        translation: Vec3::ZERO,
+        scale:       Vec3::ZERO,
+        rotation:    Vec3::ZERO,
+    };
+
+    // the motion that gets from `from` to `to` in `seconds`
+    pub fn between(from: &Transform, to: &Transform, seconds: f32) -> Self
+    {
+        let turn = to.rotation * from.rotation.inverse();
+        // the short way round again, so the angle stays under half a turn
+        let turn = if turn.w < 0.0 { -turn } else { turn };
+        Self {
+            translation: (to.translation - from.translation) / seconds,
+            scale:       (to.scale - from.scale) / seconds,
+            rotation:    turn.to_scaled_axis() / seconds,
+        }
+    }
+}
+
+// the difference between two motions, rotations are subtracted as angular velocities
+impl Sub for Motion
+{
+    type Output = Motion;
+
+    fn sub(self, other: Motion) -> Motion
+    {
+        Motion {
+            translation: self.translation - other.translation,
+            scale:       self.scale - other.scale,
+            rotation:    self.rotation - other.rotation,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests
+{
+    use std::f32::consts::{FRAC_PI_2, FRAC_PI_4, PI};
+
+    use super::*;
+
+    fn assert_matrix(actual: Mat4, expected: Mat4)
+    {
+        assert!(
+            actual.abs_diff_eq(expected, 1e-4),
+            "{:?} is not {:?}",
+            actual,
+            expected
+        );
+    }
+
+    fn turned(rotation: Quat) -> Transform
+    {
+        Transform {
+            translation: Vec3::ZERO,
+            scale: Vec3::ONE,
+            rotation,
+        }
+    }
+
+    #[test]
+    fn round_trip()
+    {
+        // the same projection main builds, which flips y, times a tile scaled and moved into place
+        let projection = Mat4::orthographic_rh_gl(0.0, 5.0, 5.0, 0.0, -10.0, 10.0)
+            * Mat4::from_scale(Vec3::new(9.0 / 16.0, 1.0, 1.0));
+        let tile = Mat4::from_translation(Vec3::new(1.78, 1.2, 5.0))
+            * Mat4::from_scale(Vec3::new(1.78 * 0.85, 0.85, 1.0));
+        for matrix in [
+            Mat4::IDENTITY,
+            projection,
+            projection * tile,
+            // a flipped tile turned a little, the way the ux animates one
+            Mat4::from_rotation_z(0.3) * Mat4::from_scale(Vec3::new(1.0, -2.0, 1.0)),
+        ]
+        {
+            assert_matrix(Transform::from_matrix(matrix).matrix(), matrix);
+        }
+    }
+
+    #[test]
+    fn slerp_midpoint()
+    {
+        let from = turned(Quat::IDENTITY);
+        let to = turned(Quat::from_rotation_z(FRAC_PI_2));
+        let halfway = from.interpolate(&to, 0.5);
+        assert_matrix(halfway.matrix(), Mat4::from_rotation_z(FRAC_PI_4));
+        // a slerp keeps the tile in shape, a component wise lerp would shrink it by cos 45°
+        assert!((halfway.matrix().x_axis.length() - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn interpolate_takes_the_short_way_round()
+    {
+        let from = turned(Quat::from_rotation_z(170f32.to_radians()));
+        let to = turned(Quat::from_rotation_z(-170f32.to_radians()));
+        // through 180°, not back through 0°
+        assert_matrix(
+            from.interpolate(&to, 0.5).matrix(),
+            Mat4::from_rotation_z(PI),
+        );
+        // the same rotation written as -q is still the short way
+        let to = turned(-Quat::from_rotation_z(-170f32.to_radians()));
+        assert_matrix(
+            from.interpolate(&to, 0.5).matrix(),
+            Mat4::from_rotation_z(PI),
+        );
+    }
+
+    #[test]
+    fn motion_between()
+    {
+        let from = Transform {
+            translation: Vec3::new(1.0, 2.0, 0.0),
+            scale:       Vec3::ONE,
+            rotation:    Quat::from_rotation_z(170f32.to_radians()),
+        };
+        let to = Transform {
+            translation: Vec3::new(3.0, 2.0, 0.0),
+            scale:       Vec3::new(2.0, 2.0, 1.0),
+            rotation:    Quat::from_rotation_z(-170f32.to_radians()),
+        };
+        let motion = Motion::between(&from, &to, 0.5);
+        assert!(motion
+            .translation
+            .abs_diff_eq(Vec3::new(4.0, 0.0, 0.0), 1e-5));
+        assert!(motion.scale.abs_diff_eq(Vec3::new(2.0, 2.0, 0.0), 1e-5));
+        // 20° the short way, in half a second
+        assert!(motion
+            .rotation
+            .abs_diff_eq(Vec3::new(0.0, 0.0, 40f32.to_radians()), 1e-4));
+        assert_matrix(from.moved(&motion, 0.5).matrix(), to.matrix());
+    }
+}
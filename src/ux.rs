@@ -1,26 +1,57 @@
-use std::{collections::HashMap, sync::Arc, time::Duration};
+use std::{collections::HashMap, ops::Range, sync::Arc, time::Duration};
 
 use glam::{Affine3A, Mat4, Vec3, Vec4};
-use glium::{Display, Frame, Surface};
+use glium::{
+    uniforms::{MagnifySamplerFilter, MinifySamplerFilter},
+    Display, Frame, Surface,
+};
 use tokio::time::Instant;
 
-use crate::data::{Item, Set};
+use crate::{
+    animation::Animation,
+    data::{Item, Set},
+    easing::Easing,
+    metrics::Metrics,
+    navigation::{self, Navigation, Viewport},
+    palette::Palette,
+    prefetch::{Direction, Prefetcher},
+    text::TextRenderer,
+    texture::{Priority, TextureCache},
+    transform::Transform,
+};
 
 pub struct Grid
 {
     pub vert_offset: Lerper,
     pub rows:        Vec<Row>,
     pub selection:   usize,
+    pub prefetcher:  Prefetcher,
+    pub navigation:  Navigation,
+    // refreshed every frame from the window size
+    pub viewport:    Viewport,
+    // how many rows the grid is scrolled down, fractional when a row straddles the top edge
+    pub scroll:      f32,
 }
 
 impl Grid
 {
-    pub fn new() -> Self
+    // rows are a title band above a line of tiles one unit high
+    pub const ROW_HEIGHT: f32 = 1.0 + Row::TITLE_HEIGHT;
+
+    pub fn new(prefetcher: Prefetcher, navigation: Navigation) -> Self
     {
         Self {
-            vert_offset: Lerper::new(),
-            rows:        vec![],
-            selection:   0,
+            vert_offset: Lerper::new(
+                Mat4::IDENTITY,
+                Duration::from_millis(300),
+                Easing::CubicInOut,
+            ),
+            rows: vec![],
+            selection: 0,
+            prefetcher,
+            navigation,
+            viewport: Viewport::default(),
+            scroll: 0.0,
         }
     }
 
@@ -41,79 +72,170 @@ impl Grid
         frame: &mut Frame,
         projection: Mat4,
         context: Arc<Renderers>,
-        texture_cache: &mut HashMap<String, glium::texture::SrgbTexture2d>,
+        texture_cache: &TextureCache,
     )
     {
-        let mut matrix = projection * self.vert_offset.lerp();
-        for row in &self.rows
+        let title_width = self.viewport.columns * 1.78 - 2.0 * Row::TITLE_INDENT;
+        for (index, row) in self.rows.iter().enumerate()
         {
-            row.draw(frame, matrix, context.clone(), texture_cache);
-            let next = Affine3A::from_translation(Vec3::new(0.0, 1.0, 0.0));
-            matrix = matrix * next;
+            let matrix = self.row_matrix(projection, index);
+            row.draw(frame, matrix, context.clone(), texture_cache, title_width);
         }
     }
 
-    pub fn up(&mut self)
+    // where the focused tile is on screen right now, exactly as it is drawn
+    pub fn focus_matrix(&self, projection: Mat4) -> Option<Mat4>
+    {
+        let row = self.rows.get(self.selection)?;
+        let tile = row.tiles.get(row.selection)?;
+        let matrix = row.tile_matrix(self.row_matrix(projection, self.selection), row.selection);
+        Some(tile.matrix(matrix))
+    }
+
+    fn row_matrix(&self, projection: Mat4, index: usize) -> Mat4
+    {
+        projection
+            * self.vert_offset.value()
+            * Mat4::from_translation(Vec3::new(0.0, index as f32 * Self::ROW_HEIGHT, 0.0))
+    }
+
+    // the tile the user is on
+    pub fn focus(&self) -> Option<&Tile>
+    {
+        self.rows
+            .get(self.selection)
+            .and_then(|row| row.tiles.get(row.selection))
+    }
+
+    // ranks every image url by how soon the user will see it
+    pub fn priorities(&self) -> HashMap<String, Priority>
     {
-        if self.vert_offset.is_active()
+        let mut priorities: HashMap<String, Priority> = HashMap::new();
+        let rows = self.visible_rows();
+        for (row_index, row) in self.rows.iter().enumerate()
         {
-            return;
+            let tiles = row.visible(self.viewport.columns);
+            for (tile_index, tile) in row.tiles.iter().enumerate()
+            {
+                let priority = Priority {
+                    hidden:   !rows.contains(&row_index) || !tiles.contains(&tile_index),
+                    distance: row_index.abs_diff(self.selection)
+                        + tile_index.abs_diff(row.selection),
+                };
+                let entry = priorities
+                    .entry(tile.item.image_url.clone())
+                    .or_insert(priority);
+                *entry = (*entry).min(priority);
+            }
         }
+        priorities
+    }
+
+    // how much of the art on screen has been uploaded, 1.0 once every tile in view has its art
+    pub fn screen_progress(&self, texture_cache: &TextureCache) -> f32
+    {
+        let priorities = self.priorities();
+        let visible: Vec<&String> = priorities
+            .iter()
+            .filter(|(_, priority)| !priority.hidden)
+            .map(|(url, _)| url)
+            .collect();
+        if visible.is_empty()
+        {
+            return 0.0;
+        }
+        let uploaded = visible
+            .iter()
+            .filter(|url| texture_cache.art.contains_key(**url))
+            .count();
+        uploaded as f32 / visible.len() as f32
+    }
+
+    pub fn up(&mut self)
+    {
         if self.selection > 0
         {
             self.unselect();
             self.selection = self.selection.clone() - 1;
-            self.vert_offset.next(self.offset());
+            self.scroll_to_selection();
             self.select();
+            self.prefetcher.record(Direction::Up);
         }
     }
 
     pub fn down(&mut self)
     {
-        if self.vert_offset.is_active()
-        {
-            return;
-        }
-        if self.selection < self.rows.len() - 1
+        if self.selection + 1 < self.rows.len()
         {
             self.unselect();
             self.selection = self.selection.clone() + 1;
-            self.vert_offset.next(self.offset());
+            self.scroll_to_selection();
             self.select();
+            self.prefetcher.record(Direction::Down);
         }
     }
 
     pub fn left(&mut self)
     {
         let row = self.rows.get_mut(self.selection);
-        match row
+        let moved = match row
         {
-            None =>
-            {}
-            Some(row) =>
-            {
-                row.left();
-            }
+            None => false,
+            Some(row) => row.left(&self.navigation, self.viewport.columns),
+        };
+        if moved
+        {
+            self.prefetcher.record(Direction::Left);
         }
     }
 
     pub fn right(&mut self)
     {
         let row = self.rows.get_mut(self.selection);
-        match row
+        let moved = match row
         {
-            None =>
-            {}
-            Some(row) =>
-            {
-                row.right();
-            }
+            None => false,
+            Some(row) => row.right(&self.navigation, self.viewport.columns),
+        };
+        if moved
+        {
+            self.prefetcher.record(Direction::Right);
         }
     }
 
+    // the urls the prefetcher wants warmed, given where the user is heading
+    pub fn prefetch(&mut self, texture_cache: &TextureCache) -> Vec<String>
+    {
+        let rows = self.visible_rows();
+        self.prefetcher.plan(
+            &self.rows,
+            self.selection,
+            rows,
+            self.viewport.columns,
+            texture_cache,
+        )
+    }
+
+    // the rows at least partly on screen
+    pub fn visible_rows(&self) -> Range<usize>
+    {
+        navigation::visible(self.scroll, self.viewport.rows, self.rows.len())
+    }
+
     pub fn offset(&self) -> Mat4
     {
-        Mat4::from_translation(Vec3::new(0.0, -(self.selection as f32 * 1.0), 0.0))
+        Mat4::from_translation(Vec3::new(0.0, -self.scroll * Self::ROW_HEIGHT, 0.0))
+    }
+
+    fn scroll_to_selection(&mut self)
+    {
+        self.scroll = self.navigation.scroll_rows(
+            self.scroll,
+            self.selection,
+            self.rows.len(),
+            self.viewport.rows,
+        );
+        self.vert_offset.next(self.offset());
     }
 
     fn unselect(&mut self)
@@ -158,17 +280,26 @@ pub struct Row
     pub tiles:     Vec<Tile>,
     pub selection: usize,
     pub offset:    Lerper,
+    // how many tiles the row is scrolled left, fractional when a tile straddles the edge
+    pub scroll:    f32,
 }
 
 impl Row
 {
+    // the band above the tiles that holds the set's title
+    pub const TITLE_HEIGHT: f32 = 0.3;
+    // lines the title up with the left edge of the first tile's art
+    const TITLE_INDENT: f32 = 0.15;
+    const TITLE_SIZE: f32 = 0.2;
+
     pub fn new(set: Set, tiles: Vec<Tile>) -> Self
     {
         Self {
             set,
             tiles,
             selection: 0,
-            offset: Lerper::new(),
+            offset: Lerper::new(Mat4::IDENTITY, Duration::from_millis(250), Easing::CubicOut),
+            scroll: 0.0,
         }
     }
 
@@ -177,57 +308,79 @@ impl Row
         frame: &mut Frame,
         matrix: Mat4,
         context: Arc<Renderers>,
-        texture_cache: &mut HashMap<String, glium::texture::SrgbTexture2d>,
+        texture_cache: &TextureCache,
+        title_width: f32,
     )
     {
-        let mut matrix = matrix;
+        // the title stays put while the tiles scroll underneath it
+        let title = matrix
+            * Mat4::from_translation(Vec3::new(
+                Self::TITLE_INDENT,
+                (Self::TITLE_HEIGHT - Self::TITLE_SIZE) / 2.0,
+                0.0,
+            ));
+        context.text_renderer.draw(
+            frame,
+            title,
+            &self.set.title,
+            Self::TITLE_SIZE,
+            title_width,
+            Vec4::ONE,
+        );
+
+        for (index, tile) in self.tiles.iter().enumerate()
+        {
+            let matrix = self.tile_matrix(matrix, index);
+            tile.draw(frame, matrix, context.clone(), texture_cache);
+        }
+    }
+
+    // where tile `index` goes, before the tile adds its own margin and lift
+    fn tile_matrix(&self, matrix: Mat4, index: usize) -> Mat4
+    {
+        let mut matrix = matrix * Mat4::from_translation(Vec3::new(0.0, Self::TITLE_HEIGHT, 0.0));
 
         // Yippie!  A Hard coded fix aspect value of 1.78.... I hope this never comes back to bite me!
         let tile_aspect_fix = Affine3A::from_scale(Vec3::new(1.78, 1.0, 1.0));
         matrix = matrix * tile_aspect_fix;
 
-        matrix = matrix * self.offset.lerp();
+        matrix = matrix * self.offset.value();
 
-        let next = Affine3A::from_translation(Vec3::new(1.0, 0.0, 0.0));
-        for tile in &self.tiles
-        {
-            tile.draw(frame, matrix, context.clone(), texture_cache);
-            matrix = matrix * next;
-        }
+        matrix * Mat4::from_translation(Vec3::new(index as f32, 0.0, 0.0))
     }
 
-    pub fn left(&mut self)
+    // the tiles at least partly on screen
+    pub fn visible(&self, columns: f32) -> Range<usize>
     {
-        if self.offset.is_active()
-        {
-            return;
-        }
+        navigation::visible(self.scroll, columns, self.tiles.len())
+    }
 
+    // true if the selection moved
+    pub fn left(&mut self, navigation: &Navigation, columns: f32) -> bool
+    {
         if self.selection == 0
         {
-            return;
+            return false;
         }
         self.unselect();
         self.selection = self.selection.clone() - 1;
-        self.offset.next(self.calc_offset());
+        self.scroll_to_selection(navigation, columns);
         self.select();
+        true
     }
 
-    pub fn right(&mut self)
+    // true if the selection moved
+    pub fn right(&mut self, navigation: &Navigation, columns: f32) -> bool
     {
-        if self.offset.is_active()
+        if self.selection + 1 >= self.tiles.len()
         {
-            return;
-        }
-
-        if self.selection == self.tiles.len() - 1
-        {
-            return;
+            return false;
         }
         self.unselect();
         self.selection = self.selection.clone() + 1;
-        self.offset.next(self.calc_offset());
+        self.scroll_to_selection(navigation, columns);
         self.select();
+        true
     }
 
     fn unselect(&mut self)
@@ -258,27 +411,57 @@ impl Row
 
     fn find_selection(&mut self) -> Option<&mut Tile> { self.tiles.get_mut(self.selection) }
 
-    fn calc_offset(&self) -> Mat4
+    fn scroll_to_selection(&mut self, navigation: &Navigation, columns: f32)
     {
-        Mat4::from_translation(Vec3::new(-(self.selection as f32 * 1.0), 0.0, 0.0))
+        self.scroll =
+            navigation.scroll_columns(self.scroll, self.selection, self.tiles.len(), columns);
+        self.offset.next(self.calc_offset());
     }
+
+    fn calc_offset(&self) -> Mat4 { Mat4::from_translation(Vec3::new(-self.scroll, 0.0, 0.0)) }
 }
 
 pub struct Tile
 {
     pub item:     Item,
     pub selected: Lerper,
+    // how visible the title and details over the art are
+    caption:      Animation<f32>,
+    // how far the focus glow has grown, 0.0 to 1.0
+    glow:         Animation<f32>,
 }
 
 impl Tile
 {
+    const CAPTION_DETAILS: f32 = 0.09;
+    const CAPTION_FADE: Duration = Duration::from_millis(250);
+    const CAPTION_PADDING: f32 = 0.06;
+    const CAPTION_TITLE: f32 = 0.13;
+    // the caption waits until the user has settled on a tile, then fades in
+    const DWELL: Duration = Duration::from_millis(600);
+    // how long it takes the preview and then the art to fade in once they arrive
+    const FADE: Duration = Duration::from_millis(300);
+    // how far the focus glow reaches past the edge of the selected tile
+    const GLOW: f32 = 0.04;
     const MARGIN: f32 = 0.15;
 
     pub fn new(item: Item) -> Self
     {
         Self {
             item,
-            selected: Lerper::new(),
+            // a spring gives the lift a quick start and a soft landing
+            selected: Lerper::new(
+                Mat4::IDENTITY,
+                Duration::from_millis(400),
+                Easing::Spring { omega: 10.0 },
+            ),
+            caption: Animation::new(0.0, Self::CAPTION_FADE, Easing::Linear),
+            // the glow grows in with the lift
+            glow: Animation::new(
+                0.0,
+                Duration::from_millis(400),
+                Easing::Spring { omega: 10.0 },
+            ),
         }
     }
 
@@ -288,129 +471,379 @@ impl Tile
         let lift = Mat4::from_translation(Vec3::new(-Self::MARGIN / 4.0, -Self::MARGIN / 4.0, 5.0));
         mat = lift * mat;
         self.selected.next(mat);
+        self.caption.next_after(1.0, Self::DWELL);
+        self.glow.next(1.0);
+    }
+
+    pub fn unselect(&mut self)
+    {
+        self.selected.next(Mat4::IDENTITY.clone());
+        self.caption.next(0.0);
+        self.glow.next(0.0);
+    }
+
+    // the tile's own margin and selection lift on top of where the row put it
+    pub fn matrix(&self, matrix: Mat4) -> Mat4
+    {
+        let margin = Affine3A::from_scale(Vec3::new(1.0 - Self::MARGIN, 1.0 - Self::MARGIN, 1.0));
+        let matrix = matrix * margin;
+        let offset =
+            Affine3A::from_translation(Vec3::new(Self::MARGIN / 2.0, Self::MARGIN / 2.0, 0.0));
+        let matrix = matrix * offset;
+
+        matrix * self.selected.value()
     }
 
-    pub fn unselect(&mut self) { self.selected.next(Mat4::IDENTITY.clone()); }
+    pub fn is_selected(&self) -> bool { self.selected.end != Mat4::IDENTITY }
 
     pub fn draw(
         &self,
         frame: &mut Frame,
         matrix: Mat4,
         context: Arc<Renderers>,
-        texture_cache: &mut HashMap<String, glium::texture::SrgbTexture2d>,
+        texture_cache: &TextureCache,
     )
     {
-        let margin = Affine3A::from_scale(Vec3::new(1.0 - Self::MARGIN, 1.0 - Self::MARGIN, 1.0));
-        let matrix = matrix * margin;
-        let offset =
-            Affine3A::from_translation(Vec3::new(Self::MARGIN / 2.0, Self::MARGIN / 2.0, 0.0));
-        let matrix = matrix * offset;
+        let matrix = self.matrix(matrix);
 
-        let matrix = matrix * self.selected.lerp();
+        let url = &self.item.image_url;
+        let palette = texture_cache.palettes.get(url);
 
-        match texture_cache.get(&self.item.image_url.clone())
+        // the focus glow is a slab of the art's dominant color peeking out from behind the
+        // tile, it grows in as the tile lifts.  The row stretches x by 1.78 so x is scaled back
+        let glow = Self::GLOW * self.glow.value();
+        if let (true, Some(palette)) = (glow > 0.0, palette)
         {
-            Some(_) =>
-            {
-                // we remove the texture, use it then insert it back in the texture_cache...
-                // I wrestled with grabbing a reference, however, gave up the battle with the borrow checker
-                // and determined to instead to utilize this 'hack' ... at least for now
-                let texture = texture_cache.remove(&self.item.image_url).unwrap();
-                context.texture_tile_renderer.draw(frame, matrix, &texture);
-                texture_cache.insert(self.item.image_url.clone(), texture);
-            }
-            None =>
-            {
-                context
-                    .color_tile_renderer
-                    .draw(frame, matrix, Vec4::from((1.0, 1.0, 1.0, 0.75)));
-            }
+            let behind = Mat4::from_translation(Vec3::new(-glow / 1.78, -glow, -0.01))
+                * Mat4::from_scale(Vec3::new(1.0 + 2.0 * glow / 1.78, 1.0 + 2.0 * glow, 1.0));
+            context.color_tile_renderer.draw(
+                frame,
+                matrix * behind,
+                palette.dominant().extend(1.0),
+            );
+        }
+
+        // each layer fades in over the one before it as it arrives: the tinted placeholder,
+        // then the blurred preview, then the art.  Layers are nudged towards the viewer so they
+        // aren't rejected by the depth test against the one underneath.  Nothing is drawn
+        // under a layer that has finished fading in
+        let preview = texture_cache.previews.get(url);
+        let art = texture_cache.lookup(url);
+        let preview_opacity = preview.map_or(0.0, |preview| preview.opacity(Self::FADE));
+        let art_opacity = art.map_or(0.0, |art| art.opacity(Self::FADE));
+        if preview_opacity < 1.0 && art_opacity < 1.0
+        {
+            // tinted with the art's average color when a previous run saved it
+            let color = palette
+                .map(|palette| palette.average())
+                .unwrap_or(Vec3::ONE);
+            context
+                .color_tile_renderer
+                .draw(frame, matrix, color.extend(0.75));
+        }
+        if let (Some(preview), true) = (preview, art_opacity < 1.0)
+        {
+            let matrix = matrix * Mat4::from_translation(Vec3::new(0.0, 0.0, 0.005));
+            context.texture_tile_renderer.draw(
+                frame,
+                matrix,
+                &preview.texture,
+                1.0,
+                preview_opacity,
+            );
+        }
+        if let Some(art) = art
+        {
+            let matrix = matrix * Mat4::from_translation(Vec3::new(0.0, 0.0, 0.01));
+            context
+                .texture_tile_renderer
+                .draw(frame, matrix, &art.texture, 1.0, art_opacity);
+        }
+
+        let opacity = clamp(self.caption.value());
+        if opacity > 0.0
+        {
+            self.draw_caption(frame, matrix, context, opacity);
+        }
+    }
+
+    // the title and details over the bottom of the tile, with a drop shadow so they read
+    // against any art.  The row stretches x by 1.78 so it is undone to keep the text in shape
+    fn draw_caption(&self, frame: &mut Frame, matrix: Mat4, context: Arc<Renderers>, opacity: f32)
+    {
+        let title = match &self.item.title
+        {
+            Some(title) => title,
+            None => return,
+        };
+        let details = self.item.details();
+        let matrix = matrix * Mat4::from_scale(Vec3::new(1.0 / 1.78, 1.0, 1.0));
+        let width = 1.78 - 2.0 * Self::CAPTION_PADDING;
+
+        let mut lines = vec![(title.as_str(), Self::CAPTION_TITLE, Vec3::ONE)];
+        if !details.is_empty()
+        {
+            lines.push((details.as_str(), Self::CAPTION_DETAILS, Vec3::splat(0.8)));
+        }
+        let mut y =
+            1.0 - Self::CAPTION_PADDING - lines.iter().map(|(_, size, _)| size).sum::<f32>();
+        for (text, size, color) in lines
+        {
+            let shadow =
+                Mat4::from_translation(Vec3::new(Self::CAPTION_PADDING + 0.01, y + 0.01, 0.02));
+            context.text_renderer.draw(
+                frame,
+                matrix * shadow,
+                text,
+                size,
+                width,
+                Vec4::new(0.0, 0.0, 0.0, 0.6 * opacity),
+            );
+            let line = Mat4::from_translation(Vec3::new(Self::CAPTION_PADDING, y, 0.03));
+            context.text_renderer.draw(
+                frame,
+                matrix * line,
+                text,
+                size,
+                width,
+                color.extend(opacity),
+            );
+            y += size;
         }
     }
 }
 
-pub fn lerp(a: &Mat4, b: &Mat4, value: f32) -> Mat4
+// a horizontal gauge positioned in normalized device coordinates so it stays put no matter
+// how the grid is scrolled, and pushed to the near plane so it is drawn over the tiles
+pub struct Bar
 {
-    let value = clamp(value);
-    a.clone() + ((b.clone() - a.clone()) * value)
+    pub x:      f32,
+    pub y:      f32,
+    pub width:  f32,
+    pub height: f32,
 }
 
-pub fn clamp(v: f32) -> f32
+impl Bar
 {
-    if v < 0.0
+    pub fn draw(&self, frame: &mut Frame, context: Arc<Renderers>, fraction: f32, color: Vec4)
     {
-        return 0.0;
-    }
-    else if v > 1.0
-    {
-        return 1.0;
+        let track = self.matrix(self.width, -0.99);
+        context
+            .color_tile_renderer
+            .draw(frame, track, Vec4::from((1.0, 1.0, 1.0, 0.25)));
+
+        let fill = self.matrix(self.width * clamp(fraction), -0.991);
+        context.color_tile_renderer.draw(frame, fill, color);
     }
-    else
+
+    fn matrix(&self, width: f32, depth: f32) -> Mat4
     {
-        return v;
+        Mat4::from_translation(Vec3::new(self.x, self.y, depth))
+            * Mat4::from_scale(Vec3::new(width, self.height, 1.0))
     }
 }
 
-pub struct Lerper
+// a thin bar along the bottom of the screen that fills up as the cacher works through its queue
+pub struct ProgressBar;
+
+impl ProgressBar
 {
-    pub begin:      Mat4,
-    pub end:        Mat4,
-    pub start_time: Instant,
-    pub duration:   Duration,
+    const BAR: Bar = Bar {
+        x:      -0.9,
+        y:      -0.95,
+        width:  1.8,
+        height: 0.01,
+    };
+
+    pub fn draw(frame: &mut Frame, context: Arc<Renderers>, fraction: f32)
+    {
+        Self::BAR.draw(frame, context, fraction, Vec4::from((1.0, 1.0, 1.0, 1.0)));
+    }
 }
 
-impl Lerper
+// the cacher's vital signs as a stack of labelled gauges in the upper right corner, toggled
+// with F3.  From the top: the share of tiles drawn with their art in memory, the share of
+// requests coalesced into one already made, disk hit rate,
+// queue depth, error rate, mean download latency (out of a second) and mean decode time
+// (out of 100ms)
+pub struct StatsOverlay;
+
+impl StatsOverlay
 {
-    pub fn new() -> Self
+    const GAP: f32 = 0.03;
+    const HEIGHT: f32 = 0.02;
+    // the labels sit in a column to the left of the gauges
+    const LABEL_X: f32 = 0.3;
+    const WIDTH: f32 = 0.4;
+    const X: f32 = 0.55;
+    const Y: f32 = 0.9;
+
+    pub fn draw(frame: &mut Frame, context: Arc<Renderers>, metrics: &Metrics)
     {
-        Self {
-            begin:      Mat4::IDENTITY.clone(),
-            end:        Mat4::IDENTITY.clone(),
-            start_time: Instant::now(),
-            duration:   Duration::from_millis(200),
+        let requested = Metrics::get(&metrics.admitted).max(1) as f32;
+        let errors = Metrics::get(&metrics.download_errors) + Metrics::get(&metrics.decode_errors);
+        let gauges = [
+            (
+                "memory hits",
+                metrics.memory_hit_rate(),
+                Vec4::from((0.3, 0.9, 0.9, 1.0)),
+            ),
+            (
+                "coalesced",
+                metrics.coalesced_rate(),
+                Vec4::from((0.2, 0.6, 1.0, 1.0)),
+            ),
+            (
+                "disk hits",
+                metrics.disk_hit_rate(),
+                Vec4::from((0.2, 0.9, 0.3, 1.0)),
+            ),
+            (
+                "queue",
+                metrics.queue_depth() as f32 / requested,
+                Vec4::from((1.0, 0.6, 0.1, 1.0)),
+            ),
+            (
+                "errors",
+                errors as f32 / requested,
+                Vec4::from((1.0, 0.2, 0.2, 1.0)),
+            ),
+            (
+                "download",
+                metrics.download_latency.mean().as_secs_f32(),
+                Vec4::from((0.7, 0.3, 1.0, 1.0)),
+            ),
+            (
+                "decode",
+                metrics.decode_time.mean().as_secs_f32() * 10.0,
+                Vec4::from((1.0, 0.9, 0.2, 1.0)),
+            ),
+        ];
+        // the gauges are placed in normalized device coordinates, which stretch with the window
+        // and point y up.  The text renderer wants y down and square units
+        let (width, height) = frame.get_dimensions();
+        let aspect = height as f32 / width as f32;
+        for (index, (label, fraction, color)) in gauges.iter().enumerate()
+        {
+            let bar = Bar {
+                x:      Self::X,
+                y:      Self::Y - index as f32 * Self::GAP,
+                width:  Self::WIDTH,
+                height: Self::HEIGHT,
+            };
+            bar.draw(frame, context.clone(), *fraction, *color);
+
+            let size = Self::HEIGHT * 1.4;
+            let top = bar.y + bar.height + (size - bar.height) / 2.0;
+            let matrix = Mat4::from_translation(Vec3::new(Self::LABEL_X, top, -0.99))
+                * Mat4::from_scale(Vec3::new(aspect, -1.0, 1.0));
+            context.text_renderer.draw(
+                frame,
+                matrix,
+                label,
+                size,
+                (Self::X - Self::LABEL_X - 0.02) / aspect,
+                Vec4::new(1.0, 1.0, 1.0, 0.9),
+            );
         }
     }
+}
+
+// a small amber light in the upper left corner, lit while the bandwidth cap is holding back
+// downloads.  It breathes so it reads as a status rather than a stuck pixel
+pub struct ThrottleIndicator;
 
-    // make this the next location we will lerp to and start the timer
-    pub fn next(&mut self, end: Mat4)
+impl ThrottleIndicator
+{
+    const SIZE: f32 = 0.03;
+    const X: f32 = -0.97;
+    const Y: f32 = 0.92;
+
+    pub fn draw(frame: &mut Frame, context: Arc<Renderers>, start: Instant)
     {
-        self.begin = self.end.clone();
-        self.end = end;
-        self.start_time = Instant::now();
+        let pulse = 0.75 + 0.25 * (start.elapsed().as_secs_f32() * 4.0).sin();
+        let matrix = Mat4::from_translation(Vec3::new(Self::X, Self::Y, -0.99))
+            * Mat4::from_scale(Vec3::new(Self::SIZE * 9.0 / 16.0, Self::SIZE, 1.0));
+        context.color_tile_renderer.draw(
+            frame,
+            matrix,
+            Vec4::from((1.0 * pulse, 0.6 * pulse, 0.1 * pulse, 1.0)),
+        );
     }
+}
+
+// the background slowly takes on a little of the focused art's average color
+pub struct BackgroundWash
+{
+    color: Animation<Vec3>,
+}
 
-    pub fn apply(&mut self, xform: Affine3A)
+impl BackgroundWash
+{
+    const BASE: [f32; 3] = [0.129, 0.588, 0.953];
+    // how long it takes to cross-fade to a new color
+    const SETTLE: Duration = Duration::from_millis(500);
+    // how much of the art's color makes it into the background
+    const STRENGTH: f32 = 0.35;
+
+    // eases towards the wash for `palette` (or the plain background without one) and returns
+    // the color to clear the frame with
+    pub fn update(&mut self, palette: Option<&Palette>) -> Vec3
     {
-        self.begin = self.end.clone();
-        self.end = self.end * xform;
-        self.start_time = Instant::now();
+        let target = match palette
+        {
+            Some(palette) => Vec3::from(Self::BASE).lerp(palette.average(), Self::STRENGTH),
+            None => Vec3::from(Self::BASE),
+        };
+        if target != self.color.end
+        {
+            self.color.next(target);
+        }
+        self.color.value()
     }
+}
 
-    pub fn set(&mut self, begin: Mat4, end: Mat4)
+impl Default for BackgroundWash
+{
+    fn default() -> Self
     {
-        self.begin = begin;
-        self.end = end;
-        self.start_time = Instant::now();
+        Self {
+            color: Animation::new(
+                Vec3::from(BackgroundWash::BASE),
+                BackgroundWash::SETTLE,
+                Easing::QuadInOut,
+            ),
+        }
     }
+}
 
-    pub fn unset(&mut self)
+pub fn lerp(a: &Mat4, b: &Mat4, value: f32) -> Mat4
+{
+    let value = clamp(value);
+    Transform::from_matrix(*a)
+        .interpolate(&Transform::from_matrix(*b), value)
+        .matrix()
+}
+
+pub fn clamp(v: f32) -> f32
+{
+    if v < 0.0
     {
-        self.begin = Mat4::IDENTITY.clone();
-        self.end = Mat4::IDENTITY.clone();
+        return 0.0;
     }
-
-    pub fn lerp(&self) -> Mat4
+    else if v > 1.0
     {
-        let elapsed = self.start_time.elapsed();
-        let v = (elapsed.as_millis() as f32 / self.duration.as_millis() as f32) as f32;
-        lerp(&self.begin, &self.end, v)
+        return 1.0;
+    }
+    else
+    {
+        return v;
     }
-
-    pub fn is_active(&self) -> bool { !self.is_done() }
-
-    pub fn is_done(&self) -> bool { return self.start_time + self.duration < Instant::now(); }
 }
 
+// every transform in the ux animates the same way as any other property
+pub type Lerper = Animation<Mat4>;
+
 #[derive(Copy, Clone)]
 pub struct TexturedVertex
 {
@@ -424,6 +857,11 @@ pub struct Vertex
     position: [f32; 2],
 }
 
+impl Vertex
+{
+    pub fn new(x: f32, y: f32) -> Self { Self { position: [x, y] } }
+}
+
 implement_vertex!(TexturedVertex, position, tex_coords);
 implement_vertex!(Vertex, position);
 
@@ -509,6 +947,8 @@ impl TileRenderer<Vertex>
                 write: true,
                 ..Default::default()
             },
+            // the placeholder and the gauge tracks are translucent
+            blend: glium::Blend::alpha_blending(),
             ..Default::default()
         };
 
@@ -526,6 +966,9 @@ impl TileRenderer<Vertex>
 
 impl TileRenderer<TexturedVertex>
 {
+    // glium clamps this to what the driver supports
+    const ANISOTROPY: u16 = 8;
+
     pub fn new(display: &Display) -> Self
     {
         let vertex1 = TexturedVertex {
@@ -575,8 +1018,11 @@ impl TileRenderer<TexturedVertex>
         in vec2 v_tex_coords;
         out vec4 color;
         uniform sampler2D tex;
+        uniform float brightness;
+        uniform float opacity;
         void main() {
-            color = texture(tex, v_tex_coords);
+            vec4 texel = texture(tex, v_tex_coords);
+            color = vec4(texel.rgb * brightness, texel.a * opacity);
         }
     "#;
 
@@ -591,7 +1037,14 @@ impl TileRenderer<TexturedVertex>
         }
     }
 
-    pub fn draw(&self, frame: &mut Frame, matrix: Mat4, texture: &glium::texture::SrgbTexture2d)
+    pub fn draw(
+        &self,
+        frame: &mut Frame,
+        matrix: Mat4,
+        texture: &glium::texture::SrgbTexture2d,
+        brightness: f32,
+        opacity: f32,
+    )
     {
         let uniforms = uniform! {
             matrix: [
@@ -601,14 +1054,22 @@ impl TileRenderer<TexturedVertex>
                 [ matrix.z_axis.x , matrix.z_axis.y, matrix.z_axis.z, matrix.z_axis.w],
                 [ matrix.w_axis.x , matrix.w_axis.y, matrix.w_axis.z, matrix.w_axis.w],
             ],
-            tex: texture,
+            tex: texture
+                .sampled()
+                .minify_filter(MinifySamplerFilter::LinearMipmapLinear)
+                .magnify_filter(MagnifySamplerFilter::Linear)
+                .anisotropy(Self::ANISOTROPY),
+            brightness: brightness,
+            opacity: opacity,
         };
+        // png and webp art may carry alpha (logos, title treatments) so blend it over the scene
         let params = glium::DrawParameters {
             depth: glium::Depth {
                 test: glium::draw_parameters::DepthTest::IfLess,
                 write: true,
                 ..Default::default()
             },
+            blend: glium::Blend::alpha_blending(),
             ..Default::default()
         };
         frame
@@ -627,6 +1088,7 @@ pub struct Renderers
 {
     pub texture_tile_renderer: TileRenderer<TexturedVertex>,
     pub color_tile_renderer:   TileRenderer<Vertex>,
+    pub text_renderer:         TextRenderer,
 }
 
 impl Renderers
@@ -634,11 +1096,13 @@ impl Renderers
     pub async fn new(
         texture_tile_renderer: TileRenderer<TexturedVertex>,
         color_tile_renderer: TileRenderer<Vertex>,
+        text_renderer: TextRenderer,
     ) -> Self
     {
         Self {
             texture_tile_renderer,
             color_tile_renderer,
+            text_renderer,
         }
     }
 }